@@ -0,0 +1,145 @@
+use cargo::{
+    CargoResult,
+    core::{PackageId, Source, SourceId},
+    util::config::Config
+};
+use copy;
+use diff::{self, GENERATED};
+use errors::{self, Code};
+use hash::hash_files;
+use manifest::{self, PackagePath};
+use serde_json;
+use state::{Origin, State};
+use std::{
+    fs,
+    io,
+    path::Path
+};
+use sync;
+use toml;
+
+/// Written by `cargo vendor`, listing the hash of every file
+const CHECKSUMS: &str = ".cargo-checksum.json";
+
+/// Turn edits made by hand in a vendor directory into managed copies.
+/// Every vendored crate is compared to its release on crates.io. Edited
+/// ones are copied to `basedir` with their edits, which are also saved as
+/// a patch file, restored in the vendor directory, and pointed to from
+/// `[patch.crates-io]` in `root_manifest`.
+pub fn adopt(config: &Config, basedir: &Path, root_manifest: &Path, vendor: &Path) -> CargoResult<()> {
+    if !vendor.is_dir() {
+        return Err(errors::error(Code::VendorNotFound, format!("{} is not a directory", vendor.display())));
+    }
+    fs::create_dir_all(basedir)?;
+
+    let source_id = SourceId::crates_io(config)?;
+    let mut source = source_id.load(config)?;
+    source.update()?;
+
+    let mut state = State::load(basedir)?;
+    let mut adopted = Vec::new();
+    // The crates adopted before a copy gets in the way are still kept
+    let mut blocked = None;
+    let mut entries = fs::read_dir(vendor)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for vendored in entries {
+        let manifest = vendored.join("Cargo.toml");
+        if !manifest.exists() {
+            continue;
+        }
+        let parsed: toml::Value = toml::from_str(&fs::read_to_string(&manifest)?)?;
+        let field = |key: &str| parsed.get("package").and_then(|package| package.get(key)).and_then(|value| value.as_str());
+        let (name, version) = match (field("name"), field("version")) {
+            (Some(name), Some(version)) => (name.to_string(), version.to_string()),
+            _ => continue
+        };
+
+        let id = PackageId::new(&name, &*version, &source_id)?;
+        let pristine = match source.download(&id) {
+            Ok(package) => package.root().to_path_buf(),
+            Err(err) => {
+                config.shell().warn(format!("skipping {} {}, it couldn't be fetched from crates.io: {}", name, version, err))?;
+                continue;
+            }
+        };
+        let mut edits = sync::edits(&pristine, &vendored, &state.ignore)?;
+        edits.changed.retain(|file| !GENERATED.contains(&file.as_str()));
+        if edits.changed.is_empty() && edits.removed.is_empty() {
+            continue;
+        }
+
+        let dir = format!("{}-{}", name, version);
+        let copy = basedir.join(&dir);
+        if copy.exists() {
+            blocked = Some(errors::error(Code::CopyExists, format!("Remove {} first", copy.display())));
+            break;
+        }
+        config.shell().status("Adopting", format!("the edits to {} {}", name, version))?;
+        copy::all(&vendored, &copy)?;
+        for file in GENERATED {
+            if copy.join(file).exists() {
+                fs::remove_file(copy.join(file))?;
+            }
+        }
+        let patch = basedir.join(format!("{}.patch", dir));
+        fs::write(&patch, diff::diff(&pristine, &vendored, &[])?)?;
+        state.record(&dir, Origin::new(&id), &pristine, &copy)?;
+
+        restore(&pristine, &vendored)?;
+        config.shell().status("Restored", format!("{}, the edits are in {}", vendored.display(), patch.display()))?;
+        adopted.push((name, copy));
+    }
+    if adopted.is_empty() {
+        state.save(basedir)?;
+        if let Some(err) = blocked {
+            return Err(err);
+        }
+        config.shell().status("Fresh", format!("nothing in {} was edited", vendor.display()))?;
+        return Ok(());
+    }
+    let contents = fs::read_to_string(root_manifest)?;
+    state.remember(root_manifest, &contents);
+    state.save(basedir)?;
+    let parsed: toml::Value = toml::from_str(&contents)?;
+    let existing = parsed.get("patch").and_then(|patch| patch.get("crates-io"));
+    for (name, _) in &adopted {
+        if existing.and_then(|patches| patches.get(name)).is_some() {
+            config.shell().warn(format!("replaced the existing [patch.crates-io] entry for {}", name))?;
+        }
+    }
+    let entries: Vec<(String, String, PackagePath)> = adopted.iter()
+        .map(|(name, copy)| (String::from("crates-io"), name.clone(), PackagePath::Path(copy.clone())))
+        .collect();
+    fs::write(root_manifest, manifest::add_patches(&contents, &entries)?)?;
+    config.shell().status("Rewrote", format!("[patch.crates-io] in {} to point to the copies", root_manifest.display()))?;
+    match blocked {
+        Some(err) => Err(err),
+        None => Ok(())
+    }
+}
+
+/// Put the original crate back into the vendor directory, with checksums cargo accepts
+fn restore(original: &Path, vendored: &Path) -> CargoResult<()> {
+    // The checksum of the .crate file doesn't change, only the ones of the files do
+    let package = fs::read_to_string(vendored.join(CHECKSUMS)).ok()
+        .and_then(|checksums| serde_json::from_str::<serde_json::Value>(&checksums).ok())
+        .and_then(|checksums| checksums.get("package").cloned())
+        .unwrap_or(serde_json::Value::Null);
+
+    fs::remove_dir_all(vendored)?;
+    copy::all(original, vendored)?;
+    if vendored.join(".cargo-ok").exists() {
+        fs::remove_file(vendored.join(".cargo-ok"))?;
+    }
+
+    let files = hash_files(vendored, &[])?.into_iter()
+        .map(|(file, hash)| (file, serde_json::Value::String(hash)))
+        .collect();
+    let mut checksums = serde_json::Map::new();
+    checksums.insert(String::from("files"), serde_json::Value::Object(files));
+    checksums.insert(String::from("package"), package);
+    fs::write(vendored.join(CHECKSUMS), serde_json::to_string(&checksums)?)?;
+    Ok(())
+}
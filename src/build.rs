@@ -0,0 +1,107 @@
+use cargo::{
+    CargoResult,
+    core::{PackageId, PackageIdSpec, Shell, SourceId},
+    util::ToUrl
+};
+use command;
+use serde_json;
+use state::State;
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+    process::Stdio
+};
+use urls;
+
+/// The package ID the way cargo writes it in its JSON messages
+fn serialized(name: &str, version: &str, source_id: &str) -> String {
+    format!("{} {} ({})", name, version, source_id)
+}
+
+/// Add the original package ID to a JSON message from cargo, if it's
+/// about one of the copies
+fn tag(line: &str, copies: &[(String, String)]) -> String {
+    let mut message: serde_json::Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(_) => return line.to_string()
+    };
+    let original = message.get("package_id")
+        .and_then(|id| id.as_str())
+        .and_then(|id| copies.iter().find(|(copy, _)| copy == id))
+        .map(|(_, original)| original.clone());
+    match (original, message.as_object_mut()) {
+        (Some(original), Some(object)) => {
+            object.insert(String::from("original_package_id"), serde_json::Value::String(original));
+        },
+        _ => return line.to_string()
+    }
+    serde_json::to_string(&message).unwrap_or_else(|_| line.to_string())
+}
+
+/// Build only the replacements and the copies, one at a time, using the
+/// workspace at `manifest`. Returns false if any of them failed. With `json`,
+/// cargo's JSON messages are passed through, and the ones about copies get an
+/// `original_package_id` field, while everything else goes to stderr.
+pub fn build(shell: &mut Shell, manifest: &Path, basedir: &Path, json: bool) -> CargoResult<bool> {
+    let state = State::load(basedir)?;
+    let mut specs = Vec::new();
+    let mut copies = Vec::new();
+    for (name, url) in &state.replace {
+        // Renamed forks come from the registry, under their own name
+        if let Some((package, _)) = urls::renamed(url) {
+            specs.push((name.clone(), package.to_string()));
+            continue;
+        }
+        let url = match urls::local_path(url) {
+            Some(path) => path.to_url()?.to_string(),
+            // Package ID specs leave out the branch, tag or rev
+            None => urls::split_reference(url).0.to_string()
+        };
+        specs.push((name.clone(), format!("{}#{}", url, name)));
+    }
+    for (dir, copy) in &state.copies {
+        if copy.source_id.is_empty() {
+            shell.warn(format!("skipping {}, it was copied by an older cargo-patch. Copy it again first", dir))?;
+            continue;
+        }
+        let source_id = SourceId::for_path(&basedir.join(dir))?;
+        let id = PackageId::new(&copy.name, &*copy.version, &source_id)?;
+        specs.push((dir.clone(), PackageIdSpec::from_package_id(&id).to_string()));
+        copies.push((
+            serialized(&copy.name, &copy.version, &source_id.to_url().to_string()),
+            serialized(&copy.name, &copy.version, &copy.source_id)
+        ));
+    }
+
+    // The shell writes to stderr, which keeps stdout clean for the JSON messages
+    let mut failed = Vec::new();
+    for (name, spec) in &specs {
+        shell.status("Building", name)?;
+        let mut cargo = command::cargo();
+        cargo.arg("build")
+            .arg("--manifest-path").arg(manifest)
+            .arg("-p").arg(spec);
+        let status = if json {
+            let mut child = cargo.arg("--message-format=json").stdout(Stdio::piped()).spawn()?;
+            if let Some(stdout) = child.stdout.take() {
+                for line in BufReader::new(stdout).lines() {
+                    println!("{}", tag(&line?, &copies));
+                }
+            }
+            child.wait()?
+        } else {
+            cargo.status()?
+        };
+        if !status.success() {
+            failed.push(name);
+        }
+    }
+
+    if failed.is_empty() {
+        shell.status("Finished", format!("all {} patched crates built", specs.len()))?;
+    } else {
+        let names: Vec<String> = failed.iter().map(|name| format!("  {}", name)).collect();
+        shell.error(format!("failed to build:\n{}", names.join("\n")))?;
+    }
+    Ok(failed.is_empty())
+}
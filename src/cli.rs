@@ -0,0 +1,578 @@
+use dates;
+use graph::Features;
+use manifest;
+use selector::{self, Selector};
+use std::{path::PathBuf, str::FromStr};
+use structopt::clap::Shell;
+
+/// A replacement given on the command line, like `name=url`, `name@1.2=url`,
+/// `name(registry=url)=url` or `name[cfg(...)]=url`.
+/// The url can also be a local directory, like `path:../foo`
+#[derive(Clone, Debug)]
+pub struct Replace {
+    pub name: String,
+    /// Which of the crates called `name` are replaced, like `name@1.2`
+    pub selector: Selector,
+    /// The target the replacement is restricted to
+    pub target: Option<String>,
+    pub url: String
+}
+impl FromStr for Replace {
+    type Err = String;
+
+    /// The cfg and the source may contain `=` themselves, so the brackets and parentheses are looked for first
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected name=url or name[cfg(...)]=url, not {:?}", value);
+        let start = match (value.find('('), value.find('['), value.find('=')) {
+            (Some(open), bracket, Some(equals)) if open < equals && bracket.map(|bracket| open < bracket).unwrap_or(true) =>
+                open + value[open..].find(')').ok_or_else(invalid)?,
+            _ => 0
+        };
+        let equals = start + value[start..].find('=').ok_or_else(invalid)?;
+        let (name, target, url) = match value[start..].find('[').map(|open| start + open) {
+            Some(open) if open < equals => {
+                let close = open + value[open..].find("]=").ok_or_else(invalid)?;
+                (&value[..open], Some(&value[open + 1..close]), &value[close + 2..])
+            },
+            _ => (&value[..equals], None, &value[equals + 1..])
+        };
+        if name.is_empty() || url.is_empty() {
+            return Err(invalid());
+        }
+        let (name, selector) = selector::parse(name)?;
+        Ok(Replace {
+            name,
+            selector,
+            target: target.map(String::from),
+            url: url.to_string()
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Copy,
+    Shadow,
+    /// Only add [patch] entries to the root manifest
+    PatchSection,
+    /// Only add paths overrides to .cargo/config.toml
+    PathsOverride
+}
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "copy" => Ok(Mode::Copy),
+            "shadow" => Ok(Mode::Shadow),
+            "patch-section" => Ok(Mode::PatchSection),
+            "paths-override" => Ok(Mode::PathsOverride),
+            _ => Err(format!("unknown mode {:?}", value))
+        }
+    }
+}
+
+/// How to change the version requirements a replacement doesn't satisfy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Relax {
+    /// Accept every version compatible with the replacement's
+    Widen,
+    /// Remove the requirement
+    Drop
+}
+impl FromStr for Relax {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "widen" => Ok(Relax::Widen),
+            "drop" => Ok(Relax::Drop),
+            _ => Err(format!("unknown way to relax versions {:?}", value))
+        }
+    }
+}
+
+/// What to do about a crate given different replacements in two places
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnConflict {
+    /// The one given last wins: flags over files, and files over Patch.toml
+    Override,
+    Error
+}
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "override" => Ok(OnConflict::Override),
+            "error" => Ok(OnConflict::Error),
+            _ => Err(format!("unknown conflict handling {:?}", value))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    Json
+}
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("unknown message format {:?}", value))
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Result<String, String> {
+    match dates::parse(value) {
+        Some(_) => Ok(value.to_string()),
+        None => Err(format!("expected a date like 2018-12-31, not {:?}", value))
+    }
+}
+
+// Cargo runs `cargo-patch patch ...` for `cargo patch ...`
+#[derive(StructOpt)]
+#[structopt(bin_name = "cargo")]
+pub enum Cargo {
+    #[structopt(name = "patch")]
+    Patch(Args)
+}
+
+/// Recursively patch dependencies without [patch]
+#[derive(StructOpt)]
+#[structopt(after_help = "EXAMPLES:
+    Replace mio everywhere in the dependency tree:
+        cargo patch --replace mio=https://github.com/redox-os/mio
+
+    Replace mio with a local checkout:
+        cargo patch --replace mio=path:forks/mio
+
+    Replace mio with a checkout outside of the workspace:
+        cargo patch --allow-outside-paths --replace mio=path:../mio
+
+    Only replace winapi when building for Windows:
+        cargo patch --replace 'winapi[cfg(windows)]=https://github.com/me/winapi-rs'
+
+    Leave the workspace alone, and patch a clone of it under target/patch:
+        cargo patch --mode shadow --replace mio=https://github.com/redox-os/mio
+
+    Read the replacements from [patch] tables in a file:
+        cargo patch --replace-toml patches.toml --profile release-safe")]
+pub struct Args {
+    /// Replace a crate with a git repository or local directory, like name=url, name=path:dir or name[cfg(...)]=url
+    #[structopt(long = "replace", value_name = "NAME=URL", raw(number_of_values = "1", display_order = "1"))]
+    pub replace: Vec<Replace>,
+    /// Read replacements like --replace from a file, one per line, or from stdin with -
+    #[structopt(long = "replace-file", value_name = "FILE", parse(from_os_str), raw(number_of_values = "1", display_order = "2"))]
+    pub replace_file: Vec<PathBuf>,
+    /// Read replacements from [patch] tables in a TOML file
+    #[structopt(long = "replace-toml", value_name = "FILE", parse(from_os_str), raw(number_of_values = "1", display_order = "2"))]
+    pub replace_toml: Vec<PathBuf>,
+    /// What to do about a crate given different replacements in two places [default: override]
+    #[structopt(long = "on-conflict", raw(possible_values = r#"&["override", "error"]"#, display_order = "3"))]
+    pub on_conflict: Option<OnConflict>,
+    /// Only use the replacements from --replace-toml files meant for this profile [default: dev]
+    #[structopt(long = "profile", raw(display_order = "3"))]
+    pub profile: Option<String>,
+    /// Continue a previously interrupted run
+    #[structopt(long = "resume", raw(conflicts_with_all = r#"&["replace", "replace_file", "replace_toml"]"#, display_order = "4"))]
+    pub resume: bool,
+    /// The manifest of the project to patch, instead of the one the current directory is in
+    #[structopt(long = "manifest-path", value_name = "PATH", parse(from_os_str), raw(display_order = "5"))]
+    pub manifest_path: Option<PathBuf>,
+    /// The workspace member whose dependencies are patched, instead of the current one
+    #[structopt(short = "p", long = "package", value_name = "MEMBER", raw(display_order = "6"))]
+    pub package: Option<String>,
+    /// Where to put the copies, instead of cargo-patch/ [default: cargo-patch]
+    #[structopt(long = "output-dir", value_name = "DIR", parse(from_os_str), raw(display_order = "7"))]
+    pub output_dir: Option<PathBuf>,
+    /// Never copy the crates with this name, which may contain * and ?, even if they lead to a replacement
+    #[structopt(long = "exclude", value_name = "NAME", raw(number_of_values = "1", display_order = "8"))]
+    pub exclude: Vec<String>,
+    /// Leave files matching this glob out of the copies, besides target/ and .git
+    #[structopt(long = "copy-ignore", value_name = "GLOB", raw(number_of_values = "1", display_order = "8"))]
+    pub copy_ignore: Vec<String>,
+    /// Only copy the files cargo would package, following the crates' include and exclude
+    #[structopt(long = "packaged-only", raw(display_order = "8"))]
+    pub packaged_only: bool,
+    /// Pick the crates to replace from the dependency tree, and enter their replacements
+    #[structopt(short = "i", long = "interactive", raw(display_order = "9"))]
+    pub interactive: bool,
+    /// Don't rewrite the [dev-dependencies] of the workspace members
+    #[structopt(long = "skip-dev-deps", raw(display_order = "15"))]
+    pub skip_dev_deps: bool,
+    /// Don't rewrite the [build-dependencies] of the workspace members
+    #[structopt(long = "skip-build-deps", raw(display_order = "16"))]
+    pub skip_build_deps: bool,
+    /// Only rewrite the [dev-dependencies] of the workspace members
+    #[structopt(long = "only-dev-deps", raw(conflicts_with_all = r#"&["skip_dev_deps", "skip_build_deps"]"#, display_order = "17"))]
+    pub only_dev_deps: bool,
+
+    /// Record who is responsible for the --replace replacements
+    #[structopt(long = "owner", raw(display_order = "10"))]
+    pub owner: Option<String>,
+    /// Record why the --replace replacements are needed
+    #[structopt(long = "reason", raw(display_order = "11"))]
+    pub reason: Option<String>,
+    /// Record a link to the upstream issue or pull request of the --replace replacements
+    #[structopt(long = "upstream", raw(display_order = "12"))]
+    pub upstream: Option<String>,
+    /// Have status warn about the --replace replacements after DATE, like 2018-12-31
+    #[structopt(long = "expires", value_name = "DATE", parse(try_from_str = "parse_date"), raw(display_order = "13"))]
+    pub expires: Option<String>,
+    /// Have status warn about the --replace replacements after DAYS days
+    #[structopt(long = "max-age", value_name = "DAYS", raw(display_order = "14"))]
+    pub max_age: Option<i64>,
+
+    /// copy rewrites the workspace, shadow rewrites a clone of it under target/patch,
+    /// patch-section only adds [patch] entries to the root manifest, and paths-override
+    /// only adds the local replacements to paths in .cargo/config.toml [default: copy]
+    #[structopt(long = "mode", raw(possible_values = r#"&["copy", "shadow", "patch-section", "paths-override"]"#, display_order = "20"))]
+    pub mode: Option<Mode>,
+    /// Widen or drop the version requirements the replacements don't satisfy
+    #[structopt(long = "relax-version", value_name = "HOW", raw(possible_values = r#"&["widen", "drop"]"#, display_order = "21"))]
+    pub relax_version: Option<Relax>,
+    /// The same as --relax-version widen
+    #[structopt(long = "force-version-compat", raw(display_order = "21"))]
+    pub force_version_compat: bool,
+    /// Redirect every version of a replaced crate, even incompatible ones, to the replacement
+    #[structopt(long = "dedupe-versions", raw(display_order = "22"))]
+    pub dedupe_versions: bool,
+    /// Point the root's [patch] and [replace] entries for the replaced crates to the replacements too
+    #[structopt(long = "merge-patch", raw(display_order = "22"))]
+    pub merge_patch: bool,
+    /// Update existing copies whose sources changed, instead of skipping them
+    #[structopt(short = "f", long = "force", raw(display_order = "23"))]
+    pub force: bool,
+    /// Copy registry packages with the manifest they were published with, Cargo.toml.orig
+    #[structopt(long = "original-manifest", raw(display_order = "24"))]
+    pub original_manifest: bool,
+    /// Rewrite manifests even if they have uncommitted changes
+    #[structopt(long = "allow-dirty", raw(display_order = "24"))]
+    pub allow_dirty: bool,
+    /// Add exceptions for the patched crates to cargo-deny's deny.toml
+    #[structopt(long = "update-deny", raw(display_order = "25"))]
+    pub update_deny: bool,
+    /// Resolve again after patching, so Cargo.lock points to the replacements
+    #[structopt(long = "update-lock", raw(display_order = "25"))]
+    pub update_lock: bool,
+    /// Add the directory of the copies to workspace.exclude in the root manifest
+    #[structopt(long = "exclude-copies", raw(display_order = "25"))]
+    pub exclude_copies: bool,
+    /// Allow replacements from local paths outside of the workspace
+    #[structopt(long = "allow-outside-paths", raw(display_order = "26"))]
+    pub allow_outside_paths: bool,
+    /// Rewrite one manifest at a time instead of all of them in parallel, to save memory
+    #[structopt(long = "low-memory", raw(display_order = "27"))]
+    pub low_memory: bool,
+    /// Print what would be copied and rewritten, without changing anything
+    #[structopt(long = "dry-run", raw(display_order = "28"))]
+    pub dry_run: bool,
+    /// Don't ask for confirmation before changing anything
+    #[structopt(short = "y", long = "yes", raw(display_order = "29"))]
+    pub yes: bool,
+    /// Explain why crates are skipped, and use verbose output (-vv very verbose)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences), raw(display_order = "30"))]
+    pub verbose: u32,
+    /// Don't print status messages
+    #[structopt(short = "q", long = "quiet", raw(conflicts_with = r#""verbose""#, display_order = "31"))]
+    pub quiet: bool,
+    /// Coloring of status messages
+    #[structopt(long = "color", value_name = "WHEN", raw(possible_values = r#"&["auto", "always", "never"]"#, display_order = "32"))]
+    pub color: Option<String>,
+    /// With json, what's copied and rewritten, and errors, are printed as JSON objects, one per line
+    #[structopt(long = "message-format", default_value = "human", raw(possible_values = r#"&["human", "json"]"#, display_order = "33"))]
+    pub message_format: MessageFormat,
+
+    /// Only follow the optional dependencies of these features, and the default ones
+    #[structopt(long = "features", value_name = "FEATURES", raw(number_of_values = "1", display_order = "40"))]
+    pub features: Vec<String>,
+    /// Follow the optional dependencies of every feature
+    #[structopt(long = "all-features", raw(display_order = "41"))]
+    pub all_features: bool,
+    /// Don't follow the optional dependencies of the default features
+    #[structopt(long = "no-default-features", raw(display_order = "42"))]
+    pub no_default_features: bool,
+    /// Don't access the network while resolving
+    #[structopt(long = "offline", raw(display_order = "43"))]
+    pub offline: bool,
+    /// Require Cargo.lock and the cache to be up to date, and patching not to change Cargo.lock
+    #[structopt(long = "frozen", raw(display_order = "44"))]
+    pub frozen: bool,
+    /// Require Cargo.lock to be up to date, and patching not to change it
+    #[structopt(long = "locked", raw(display_order = "45"))]
+    pub locked: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>
+}
+
+impl Args {
+    pub fn mode(&self) -> Mode {
+        self.mode.unwrap_or(Mode::Copy)
+    }
+    pub fn relax_version(&self) -> Option<Relax> {
+        match self.relax_version {
+            None if self.force_version_compat => Some(Relax::Widen),
+            relax => relax
+        }
+    }
+    pub fn on_conflict(&self) -> OnConflict {
+        self.on_conflict.unwrap_or(OnConflict::Override)
+    }
+    pub fn profile(&self) -> &str {
+        self.profile.as_ref().map(String::as_str).unwrap_or("dev")
+    }
+    /// The features to resolve with, if any of the flags were given.
+    /// Otherwise every optional dependency is followed.
+    pub fn features(&self) -> Option<Features> {
+        if self.features.is_empty() && !self.all_features && !self.no_default_features {
+            return None;
+        }
+        Some(Features {
+            features: self.features.clone(),
+            all_features: self.all_features,
+            no_default_features: self.no_default_features
+        })
+    }
+    /// The kinds of dependencies rewritten in the workspace members
+    pub fn kinds(&self) -> Vec<&'static str> {
+        if self.only_dev_deps {
+            return vec!["dev"];
+        }
+        manifest::KINDS.iter()
+            .cloned()
+            .filter(|&kind| !(kind == "dev" && self.skip_dev_deps) && !(kind == "build" && self.skip_build_deps))
+            .collect()
+    }
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Move edits made by hand in a vendor directory to managed copies
+    #[structopt(name = "adopt-vendor")]
+    AdoptVendor {
+        /// The vendor directory
+        #[structopt(long = "dir", value_name = "DIR", default_value = "vendor", parse(from_os_str))]
+        dir: PathBuf
+    },
+    /// Carry out a plan written by cargo patch plan
+    #[structopt(name = "apply")]
+    Apply {
+        /// The plan file
+        #[structopt(parse(from_os_str))]
+        plan: PathBuf
+    },
+    /// Restore the manifests rewritten outside of the copies, and remove the copies
+    #[structopt(name = "clean")]
+    Clean {
+        /// Don't do anything if any copy has local edits
+        #[structopt(long = "keep-modified")]
+        keep_modified: bool,
+        /// Put back manifests even if they were edited since they were rewritten
+        #[structopt(long = "force")]
+        force: bool
+    },
+    /// Print completions for a shell
+    #[structopt(name = "completions", raw(setting = "structopt::clap::AppSettings::Hidden"))]
+    Completions {
+        #[structopt(raw(possible_values = "&Shell::variants()", required_unless = r#""crate_names""#))]
+        shell: Option<Shell>,
+        /// Print the names of the crates in Cargo.lock instead, for completing --replace
+        #[structopt(long = "crate-names", raw(hidden = "true"))]
+        crate_names: bool
+    },
+    /// Show what changed in the copies compared to the sources they were copied from
+    #[structopt(name = "diff")]
+    Diff {
+        /// Only show the copies of this crate, or the copy directory like foo-1.0.0
+        #[structopt(name = "crate")]
+        name: Option<String>
+    },
+    /// Write the local edits of the copies to patch files that can be committed
+    #[structopt(name = "export")]
+    Export {
+        /// Only export the copies of this crate, or the copy directory like foo-1.0.0
+        #[structopt(name = "crate")]
+        name: Option<String>,
+        /// The directory to write the patches to, relative to the workspace root
+        #[structopt(short = "o", long = "output", value_name = "DIR", default_value = "patches", parse(from_os_str))]
+        output: PathBuf
+    },
+    /// Create a shell script that recreates the patched state without cargo-patch
+    #[structopt(name = "export-script")]
+    ExportScript {
+        /// Write the script to FILE instead of printing it
+        #[structopt(short = "o", long = "output", value_name = "FILE", parse(from_os_str))]
+        output: Option<PathBuf>
+    },
+    /// Copy a dependency into a git repository under cargo-patch/ and patch the tree to use it
+    #[structopt(name = "fork")]
+    Fork {
+        /// The crate to fork
+        #[structopt(name = "crate")]
+        name: String
+    },
+    /// Apply the patch files written by export to the copies, patching the workspace first if needed
+    #[structopt(name = "import")]
+    Import {
+        /// The directory to read the patches from, relative to the workspace root
+        #[structopt(short = "i", long = "input", value_name = "DIR", default_value = "patches", parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Show which crates are built more than once after patching, and what that costs
+    #[structopt(name = "impact")]
+    Impact {},
+    /// Show the chain of dependencies that caused each crate to be copied
+    #[structopt(name = "list")]
+    List {},
+    /// Show how patching changed the resolution compared to Cargo.lock
+    #[structopt(name = "lock-diff")]
+    LockDiff {},
+    /// Write what patching would copy and rewrite to a file, to be reviewed and applied later
+    #[structopt(name = "plan")]
+    Plan {
+        /// The file to write the plan to
+        #[structopt(short = "o", long = "output", value_name = "FILE", default_value = "patch-plan.toml", parse(from_os_str))]
+        output: PathBuf
+    },
+    /// Check whether the newest releases on crates.io make any replacements unnecessary
+    #[structopt(name = "retry-upstream")]
+    RetryUpstream {
+        /// The command that has to pass without the replacement
+        #[structopt(long = "test", default_value = "cargo test")]
+        test: String
+    },
+    /// Turn the local edits of a copy into patches against its upstream repository
+    #[structopt(name = "send", after_help = "EXAMPLES:
+    Write the edits to cargo-patch/mio-0.6.14.patches:
+        cargo patch send mio
+
+    Push them to a fork and open a pull request:
+        cargo patch send mio --push git@github.com:me/mio")]
+    Send {
+        /// The crate, or copy directory like foo-1.0.0, to send
+        #[structopt(name = "crate")]
+        name: String,
+        /// Push a branch to REMOTE and open a pull request with gh, instead of writing patches
+        #[structopt(long = "push", value_name = "REMOTE")]
+        push: Option<String>
+    },
+    /// List the replacements and why they were made
+    #[structopt(name = "status")]
+    Status {
+        /// Fail if any replacement is overdue for a review
+        #[structopt(long = "check")]
+        check: bool
+    },
+    /// Move a copy onto a new upstream version, keeping local edits
+    #[structopt(name = "sync")]
+    Sync {
+        /// The crate, or copy directory like foo-1.0.0, to sync
+        #[structopt(name = "crate")]
+        name: String
+    },
+    /// Show the dependency tree, highlighting patched crates
+    #[structopt(name = "tree")]
+    Tree {
+        /// Show the whole tree, not just the branches leading to replacements
+        #[structopt(long = "full")]
+        full: bool
+    },
+    /// Restore the manifests rewritten outside of the copies, and forget the replacements
+    #[structopt(name = "unpatch", raw(alias = r#""restore""#))]
+    Unpatch {
+        /// Remove the copies too
+        #[structopt(long = "remove-copies")]
+        remove_copies: bool,
+        /// Put back manifests even if they were edited since they were rewritten
+        #[structopt(long = "force")]
+        force: bool
+    },
+    /// Bring copies up to date with their sources, keeping the rewritten manifests
+    #[structopt(name = "update")]
+    Update {
+        /// Only update the copies of this crate, or the copy directory like foo-1.0.0
+        #[structopt(name = "crate")]
+        name: Option<String>
+    },
+    /// Check that the copies, their sources and the rewritten manifests are as cargo-patch left them
+    #[structopt(name = "verify")]
+    Verify {
+        /// Also build each replacement and copy on its own
+        #[structopt(long = "build")]
+        build: bool,
+        /// With json, cargo's JSON messages are passed through, tagged with the copies' original package IDs
+        #[structopt(long = "message-format", default_value = "human", raw(possible_values = r#"&["human", "json"]"#, requires = r#""build""#))]
+        message_format: MessageFormat
+    },
+    /// Check the copies against the hashes recorded when they were made
+    #[structopt(name = "verify-integrity")]
+    VerifyIntegrity {
+        /// Restore changed or missing files from the original sources
+        #[structopt(long = "restore")]
+        restore: bool
+    }
+}
+impl Command {
+    /// Whether the command patches the workspace, like running without one
+    pub fn patches(&self) -> bool {
+        match *self {
+            Command::Apply { .. } | Command::Fork { .. } | Command::Plan { .. } => true,
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace() {
+        let replace: Replace = "foo=https://github.com/me/foo".parse().unwrap();
+        assert_eq!(replace.name, "foo");
+        assert!(replace.selector.is_empty());
+        assert_eq!(replace.target, None);
+        assert_eq!(replace.url, "https://github.com/me/foo");
+    }
+
+    #[test]
+    fn replace_selector() {
+        let replace: Replace = "foo@1.2=path:../foo".parse().unwrap();
+        assert_eq!(replace.name, "foo");
+        assert_eq!(replace.selector.version, Some(String::from("1.2")));
+        assert_eq!(replace.url, "path:../foo");
+
+        let replace: Replace = "foo(registry=https://my-registry/index)=https://github.com/me/foo?branch=dev".parse().unwrap();
+        assert_eq!(replace.name, "foo");
+        assert_eq!(replace.selector.registry, Some(String::from("https://my-registry/index")));
+        assert_eq!(replace.url, "https://github.com/me/foo?branch=dev");
+    }
+
+    #[test]
+    fn replace_target() {
+        let replace: Replace = "winapi[cfg(windows)]=https://github.com/me/winapi-rs".parse().unwrap();
+        assert_eq!(replace.name, "winapi");
+        assert_eq!(replace.target, Some(String::from("cfg(windows)")));
+        assert_eq!(replace.url, "https://github.com/me/winapi-rs");
+
+        let replace: Replace = "winapi@0.3[cfg(target_os = \"windows\")]=url".parse().unwrap();
+        assert_eq!(replace.name, "winapi");
+        assert_eq!(replace.selector.version, Some(String::from("0.3")));
+        assert_eq!(replace.target, Some(String::from("cfg(target_os = \"windows\")")));
+        assert_eq!(replace.url, "url");
+    }
+
+    #[test]
+    fn replace_invalid() {
+        for value in &["foo", "=url", "foo=", "foo[cfg(windows)=url", "foo@=url", "foo(registry=url=x"] {
+            assert!(value.parse::<Replace>().is_err(), "{} should be invalid", value);
+        }
+    }
+}
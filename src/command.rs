@@ -0,0 +1,9 @@
+use std::{env, ffi::OsString, process::Command};
+
+/// Start building a cargo invocation. Cargo sets `$CARGO` to its own path when
+/// running subcommands, which keeps rustup toolchain overrides and hermetic
+/// setups intact, so that's preferred over whatever is first in `$PATH`.
+pub fn cargo() -> Command {
+    let program = env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    Command::new(program)
+}
@@ -0,0 +1,58 @@
+use cargo::CargoResult;
+use cli::Cargo;
+use std::{
+    collections::BTreeSet,
+    fs,
+    io,
+    path::Path
+};
+use structopt::{clap::Shell, StructOpt};
+use toml;
+
+/// Completes `--replace` with the names of the crates in Cargo.lock, on top of
+/// the generated completions, which don't know about them
+const BASH: &str = r#"
+_cargo_patch_replace() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "${COMP_WORDS[1]}" == patch && "$prev" == --replace && "$cur" != *=* ]]; then
+        COMPREPLY=($(compgen -S = -W "$(cargo patch completions --crate-names 2>/dev/null)" -- "$cur"))
+        compopt -o nospace
+        return
+    fi
+    _cargo "$@"
+}
+complete -F _cargo_patch_replace -o bashdefault -o default cargo
+"#;
+const FISH: &str = r#"
+complete -c cargo -n "__fish_seen_subcommand_from patch" -l replace -x -a "(cargo patch completions --crate-names 2>/dev/null | string replace -r '\$' =)"
+"#;
+
+/// Print the completions for `cargo patch` in `shell`
+pub fn print(shell: Shell) {
+    let mut stdout = io::stdout();
+    Cargo::clap().gen_completions_to("cargo", shell, &mut stdout);
+    match shell {
+        Shell::Bash => print!("{}", BASH),
+        Shell::Fish => print!("{}", FISH),
+        _ => ()
+    }
+}
+
+/// Print the name of every crate in a lock file, once
+pub fn crate_names(lockfile: &Path) -> CargoResult<()> {
+    if !lockfile.exists() {
+        return Ok(());
+    }
+    let parsed: toml::Value = toml::from_str(&fs::read_to_string(lockfile)?)?;
+    let names: BTreeSet<&str> = parsed.get("package")
+        .and_then(|packages| packages.as_array())
+        .map(|packages| packages.iter()
+            .filter_map(|package| package.get("name"))
+            .filter_map(|name| name.as_str())
+            .collect())
+        .unwrap_or_default();
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use ignore;
+use interrupt;
+use std::{
+    fs,
+    io,
+    path::Path
+};
+
+/// Copy a file or a whole directory
+pub fn all(src: &Path, dst: &Path) -> io::Result<()> {
+    debug_assert!(!dst.exists());
+    interrupt::check()?;
+
+    if src.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let path = entry?.path();
+            all(&path, &dst.join(path.strip_prefix(src).unwrap()))?;
+        }
+    } else {
+        fs::copy(src, dst)?;
+    }
+    Ok(())
+}
+/// Copy a package, leaving out build output, version control histories and
+/// the files matching one of the globs in `patterns`
+pub fn package(src: &Path, dst: &Path, patterns: &[String]) -> io::Result<()> {
+    fn visit(root: &Path, src: &Path, dst: &Path, patterns: &[String]) -> io::Result<()> {
+        interrupt::check()?;
+        if src.is_dir() {
+            fs::create_dir(dst)?;
+            for entry in fs::read_dir(src)? {
+                let path = entry?.path();
+                if !ignore::is_ignored(path.strip_prefix(root).unwrap(), patterns) {
+                    visit(root, &path, &dst.join(path.strip_prefix(src).unwrap()), patterns)?;
+                }
+            }
+        } else {
+            fs::copy(src, dst)?;
+        }
+        Ok(())
+    }
+    debug_assert!(!dst.exists());
+    visit(src, src, dst, patterns)
+}
@@ -0,0 +1,117 @@
+use cargo::CargoResult;
+use semver::Version;
+use std::{fs, path::Path};
+use toml;
+use toml_edit::{self, Array, ArrayOfTables, Document, InlineTable, Item, Table, Value};
+
+/// Marks the `[bans] skip` entries that cargo-patch manages
+const REASON: &str = "patched by cargo-patch";
+
+/// Update a cargo-deny configuration to allow the replacement repositories
+/// and the duplicate versions caused by patching. Entries added by a previous
+/// run are replaced, so removing a patch removes its exceptions too: the
+/// repositories allowed in `original`, the file before cargo-patch first
+/// touched it, are kept, and the others are only kept if they're in `urls`.
+/// The rest of the file, comments included, is kept as it is.
+pub fn update(path: &Path, urls: &[&str], original: &str, duplicates: &[(String, Version)]) -> CargoResult<()> {
+    let original: toml::Value = toml::from_str(original)?;
+    let allowed: Vec<&str> = original.get("sources")
+        .and_then(|sources| sources.get("allow-git"))
+        .and_then(|allow| allow.as_array())
+        .map(|allow| allow.iter().filter_map(|url| url.as_str()).collect())
+        .unwrap_or_default();
+
+    let mut document: Document = if path.exists() {
+        fs::read_to_string(path)?.parse()?
+    } else {
+        Document::new()
+    };
+    {
+        let root = document.as_table_mut();
+        if let Some(allow) = table(root, "sources").and_then(|sources| array(sources, "allow-git")) {
+            let stale: Vec<usize> = allow.iter()
+                .enumerate()
+                .filter(|(_, existing)| existing.as_str().map(|url| !allowed.contains(&url) && !urls.contains(&url)).unwrap_or(false))
+                .map(|(i, _)| i)
+                .collect();
+            for i in stale.into_iter().rev() {
+                allow.remove(i);
+            }
+            for url in urls {
+                if !allow.iter().any(|existing| existing.as_str() == Some(url)) {
+                    allow.push(*url);
+                }
+            }
+        }
+        if let Some(bans) = table(root, "bans") {
+            let skip = bans.entry("skip");
+            if skip.is_none() {
+                *skip = toml_edit::value(Array::default());
+            }
+            // Written either as `skip = [...]` or as `[[bans.skip]]` tables
+            match *skip {
+                Item::Value(Value::Array(ref mut skip)) => skip_inline(skip, duplicates),
+                Item::ArrayOfTables(ref mut skip) => skip_tables(skip, duplicates),
+                _ => ()
+            }
+        }
+    }
+    fs::write(path, document.to_string())?;
+    Ok(())
+}
+
+/// The table called `key`, created if it doesn't exist
+fn table<'a>(table: &'a mut Table, key: &str) -> Option<&'a mut Table> {
+    let item = table.entry(key);
+    if item.is_none() {
+        *item = Item::Table(Table::new());
+    }
+    item.as_table_mut()
+}
+/// The array called `key`, created if it doesn't exist
+fn array<'a>(table: &'a mut Table, key: &str) -> Option<&'a mut Array> {
+    let item = table.entry(key);
+    if item.is_none() {
+        *item = toml_edit::value(Array::default());
+    }
+    item.as_value_mut().and_then(Value::as_array_mut)
+}
+
+fn skip_inline(skip: &mut Array, duplicates: &[(String, Version)]) {
+    let managed: Vec<usize> = skip.iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            let reason = entry.as_inline_table()
+                .and_then(|entry| entry.iter().find(|(key, _)| *key == "reason").map(|(_, reason)| reason));
+            reason.and_then(Value::as_str) == Some(REASON)
+        })
+        .map(|(i, _)| i)
+        .collect();
+    for i in managed.into_iter().rev() {
+        skip.remove(i);
+    }
+    for (name, version) in duplicates {
+        let mut entry = InlineTable::default();
+        entry.get_or_insert("name", name.as_str());
+        entry.get_or_insert("version", &*format!("={}", version));
+        entry.get_or_insert("reason", REASON);
+        entry.fmt();
+        skip.push(Value::InlineTable(entry));
+    }
+}
+fn skip_tables(skip: &mut ArrayOfTables, duplicates: &[(String, Version)]) {
+    for i in (0..skip.len()).rev() {
+        let managed = skip.get(i)
+            .and_then(|entry| entry.get("reason"))
+            .and_then(|reason| reason.as_str()) == Some(REASON);
+        if managed {
+            skip.remove(i);
+        }
+    }
+    for (name, version) in duplicates {
+        let entry = skip.append(Table::new());
+        *entry.entry("name") = toml_edit::value(name.as_str());
+        *entry.entry("version") = toml_edit::value(&*format!("={}", version));
+        *entry.entry("reason") = toml_edit::value(REASON);
+    }
+}
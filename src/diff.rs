@@ -0,0 +1,179 @@
+use cargo::{CargoResult, core::Shell};
+use cli::Mode;
+use command;
+use errors::{self, Code};
+use state::State;
+use std::{
+    io,
+    path::Path,
+    fs,
+    process::Command
+};
+
+/// Files cargo adds to unpacked and vendored crates
+pub const GENERATED: &[&str] = &[".cargo-checksum.json", ".cargo-ok"];
+
+/// Diff two versions of a crate, with paths relative to the crate's root,
+/// leaving out the files called `exclude` besides the generated ones
+pub fn diff(original: &Path, edited: &Path, exclude: &[&str]) -> CargoResult<String> {
+    let output = Command::new("git")
+        .args(&["diff", "--no-index", "--no-color"])
+        .arg(original)
+        .arg(edited)
+        .output()?;
+    // git exits with 1 when there are differences
+    if output.status.code().map(|code| code > 1).unwrap_or(true) {
+        return Err(io::Error::new(io::ErrorKind::Other, format!(
+            "git diff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )).into());
+    }
+    let mut diff = String::from_utf8_lossy(&output.stdout).into_owned();
+    for dir in &[original, edited] {
+        let prefix = format!("{}/", dir.to_string_lossy().trim_left_matches('/'));
+        diff = diff.replace(&prefix, "");
+    }
+
+    // Leave out the files cargo generates
+    let mut patch = String::with_capacity(diff.len());
+    for file in diff.split("diff --git ").filter(|file| !file.is_empty()) {
+        let generated = file.lines().next()
+            .map(|header| GENERATED.iter().chain(exclude).any(|name| header.ends_with(&format!("/{}", name))))
+            .unwrap_or(false);
+        if !generated {
+            patch.push_str("diff --git ");
+            patch.push_str(file);
+        }
+    }
+    Ok(patch)
+}
+
+/// Print what changed in the copies of `name`, or in every copy, compared
+/// to the sources they were copied from
+pub fn print(shell: &mut Shell, basedir: &Path, name: Option<&str>) -> CargoResult<()> {
+    let state = State::load(basedir)?;
+    for dir in &find(&state, basedir, name)? {
+        let original = Path::new(&state.copies[dir].source);
+        if !original.exists() {
+            shell.warn(format!("can't diff {}, {} is gone", dir, original.display()))?;
+            continue;
+        }
+        print!("{}", diff(original, &basedir.join(dir), &[])?);
+    }
+    Ok(())
+}
+
+/// Find the copies of `name`, or every copy. Fails if `name` has no copies.
+fn find(state: &State, basedir: &Path, name: Option<&str>) -> CargoResult<Vec<String>> {
+    let dirs = match name {
+        Some(name) => state.find(name),
+        None => state.copies.keys().cloned().collect()
+    };
+    if dirs.is_empty() {
+        if let Some(name) = name {
+            return Err(errors::error(Code::CopyNotFound, format!("There is no copy of {} in {}", name, basedir.display())));
+        }
+    }
+    Ok(dirs)
+}
+
+/// Write the local edits of the copies of `name`, or of every copy, to
+/// `<dir>.patch` files in `out`. The rewritten manifests are left out, since
+/// they're made again when patching.
+pub fn export(shell: &mut Shell, basedir: &Path, name: Option<&str>, out: &Path) -> CargoResult<()> {
+    let state = State::load(basedir)?;
+    let mut exported = 0;
+    for dir in find(&state, basedir, name)? {
+        let copy = &state.copies[&dir];
+        let original = Path::new(&copy.source);
+        if !original.exists() {
+            shell.warn(format!("can't export {}, {} is gone", dir, original.display()))?;
+            continue;
+        }
+        let diff = diff(original, &basedir.join(&dir), &["Cargo.toml"])?;
+        if diff.is_empty() {
+            continue;
+        }
+        fs::create_dir_all(out)?;
+        let path = out.join(format!("{}.patch", dir));
+        // git apply skips everything before the first diff
+        fs::write(&path, format!("cargo-patch: {}\n\n{}", copy.id, diff))?;
+        shell.status("Wrote", path.display())?;
+        exported += 1;
+    }
+    if exported == 0 {
+        shell.status("Fresh", "there are no local edits to export")?;
+    }
+    Ok(())
+}
+
+/// Run git apply on a copy, making sure git doesn't mistake it for part of
+/// the workspace's repository
+fn apply(basedir: &Path, copy: &Path, args: &[&str], patch: &Path) -> CargoResult<bool> {
+    Ok(Command::new("git")
+        .current_dir(copy)
+        .env("GIT_CEILING_DIRECTORIES", basedir)
+        .arg("apply")
+        .args(args)
+        .arg(patch)
+        .status()?
+        .success())
+}
+
+/// Apply the patch files written by `export` in `dir` to the copies. If any
+/// of the copies don't exist yet, the project in `project` is patched first.
+pub fn import(shell: &mut Shell, basedir: &Path, dir: &Path, project: &Path, mode: Mode) -> CargoResult<()> {
+    let mut patches = Vec::new();
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map(|ext| ext == "patch").unwrap_or(false) {
+                patches.push(path);
+            }
+        }
+    }
+    patches.sort();
+    if patches.is_empty() {
+        shell.status("Fresh", format!("there are no patches in {}", dir.display()))?;
+        return Ok(());
+    }
+    let copy_of = |patch: &Path| basedir.join(patch.file_stem().expect("patch file without a name"));
+
+    if patches.iter().any(|patch| !copy_of(patch).exists()) {
+        shell.status("Patching", "the workspace first")?;
+        let mut cargo = command::cargo();
+        cargo.current_dir(project).args(&["patch", "--yes"]);
+        match mode {
+            Mode::Shadow => cargo.args(&["--mode", "shadow"]),
+            Mode::Copy | Mode::PatchSection | Mode::PathsOverride => cargo.arg("--output-dir").arg(basedir)
+        };
+        if !cargo.status()?.success() {
+            return Ok(());
+        }
+    }
+
+    let mut failed = Vec::new();
+    for patch in &patches {
+        let copy = copy_of(patch);
+        let name = copy.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        if !copy.exists() {
+            shell.warn(format!("skipping {}, patching didn't copy {}", patch.display(), name))?;
+            failed.push(name);
+            continue;
+        }
+        // Importing twice shouldn't fail
+        if apply(basedir, &copy, &["--check", "--reverse"], patch)? {
+            shell.status("Fresh", format!("{} is already applied", patch.display()))?;
+            continue;
+        }
+        if apply(basedir, &copy, &[], patch)? {
+            shell.status("Applied", format!("{} to {}", patch.display(), name))?;
+        } else {
+            failed.push(name);
+        }
+    }
+    if !failed.is_empty() {
+        shell.warn(format!("these copies couldn't be patched: {}", failed.join(", ")))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use events::{self, Event};
+use failure::{self, Context, Fail};
+use std::fmt;
+
+/// Identifiers for every kind of failure. These are printed with the error
+/// so scripts can tell them apart, and must never be renumbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    InvalidReplaceSyntax = 1,
+    InterruptedRun = 2,
+    NothingToResume = 3,
+    ReplacementNotFound = 4,
+    DirtyManifests = 5,
+    BaseDirNotFolder = 6,
+    InvalidDependency = 7,
+    MissingRootPackage = 8,
+    Interrupted = 9,
+    InvalidReplacementUrl = 10,
+    CopyNotFound = 11,
+    CopyExists = 12,
+    UnknownUpstream = 13,
+    VendorNotFound = 14,
+    OutsideSandbox = 15,
+    InvalidPatchFile = 16,
+    ModifiedCopies = 17,
+    AmbiguousCrate = 18,
+    PlanMismatch = 19,
+    MemberNotFound = 20,
+    LockfileChange = 21,
+    PullRequestNotFound = 22,
+    DownloadFailed = 23,
+    ConflictingReplacements = 24,
+    UnknownSource = 25,
+    UnsyncableSource = 26,
+    CopiesChanged = 27,
+    ReviewOverdue = 28,
+    UnexportableSource = 29,
+    UnsupportedManifest = 30
+}
+impl Code {
+    pub fn id(self) -> String {
+        format!("CP{:04}", self as u32)
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            Code::InvalidReplaceSyntax => "invalid replacement syntax",
+            Code::InterruptedRun => "a previous run was interrupted",
+            Code::NothingToResume => "no interrupted run to resume",
+            Code::ReplacementNotFound => "replacement not found in dependency graph",
+            Code::DirtyManifests => "manifests have uncommitted changes",
+            Code::BaseDirNotFolder => "output directory is not a folder",
+            Code::InvalidDependency => "invalid dependency in manifest",
+            Code::MissingRootPackage => "current package not found in dependency graph",
+            Code::Interrupted => "interrupted by user",
+            Code::InvalidReplacementUrl => "invalid replacement URL",
+            Code::CopyNotFound => "no copy of that crate",
+            Code::CopyExists => "copy already exists",
+            Code::UnknownUpstream => "upstream repository not found",
+            Code::VendorNotFound => "vendor directory not found",
+            Code::OutsideSandbox => "path outside of the project",
+            Code::InvalidPatchFile => "invalid Patch.toml or [package.metadata.patch]",
+            Code::ModifiedCopies => "copies have local edits",
+            Code::AmbiguousCrate => "several versions of that crate",
+            Code::PlanMismatch => "the plan doesn't match the workspace",
+            Code::MemberNotFound => "no workspace member with that name",
+            Code::LockfileChange => "patching would change Cargo.lock",
+            Code::PullRequestNotFound => "pull request not found",
+            Code::DownloadFailed => "couldn't download the replacement",
+            Code::ConflictingReplacements => "a crate is replaced in different ways",
+            Code::UnknownSource => "can't tell where the copy came from",
+            Code::UnsyncableSource => "only crates from registries can be synced",
+            Code::CopiesChanged => "copies or manifests don't match the records",
+            Code::ReviewOverdue => "replacements are overdue for a review",
+            Code::UnexportableSource => "the script can't fetch the copy's source",
+            Code::UnsupportedManifest => "the manifest needs a newer cargo"
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// A failure with its code, and details on what exactly went wrong. Other
+/// errors get a code with `.context(Code::...)`.
+#[derive(Debug)]
+pub struct Error {
+    code: Code,
+    details: String
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code.description())
+    }
+}
+impl Fail for Error {}
+
+/// Make an error with this code. The details may span several lines.
+pub fn error<S: Into<String>>(code: Code, details: S) -> failure::Error {
+    Error {
+        code,
+        details: details.into()
+    }.into()
+}
+
+/// Print an error, with a header line like `error[CP0004]: replacement not
+/// found in dependency graph` if it has a code, followed by the details and
+/// whatever caused it
+pub fn report(err: &failure::Error) {
+    let mut code = None;
+    let mut details = None;
+    for cause in err.causes() {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            code = Some(err.code);
+            details = Some(&err.details);
+            break;
+        }
+        if let Some(context) = cause.downcast_ref::<Context<Code>>() {
+            code = Some(*context.get_context());
+            break;
+        }
+    }
+    let mut message = match code {
+        Some(code) => code.description().to_string(),
+        None => err.to_string()
+    };
+    eprintln!("error{}: {}", code.map(|code| format!("[{}]", code.id())).unwrap_or_default(), message);
+    if let Some(details) = details {
+        if !details.is_empty() {
+            eprintln!("{}", details);
+            message.push_str(&format!("\n{}", details));
+        }
+    }
+    for (i, cause) in err.causes().enumerate() {
+        // The header already says what the error itself, or its code, is
+        let coded = cause.downcast_ref::<Error>().is_some() || cause.downcast_ref::<Context<Code>>().is_some();
+        if coded || (i == 0 && code.is_none()) {
+            continue;
+        }
+        eprintln!("  Caused by: {}", cause);
+        message.push_str(&format!("\n  Caused by: {}", cause));
+    }
+    events::emit(&Event::Error {
+        code: code.map(Code::id),
+        message: &message
+    });
+}
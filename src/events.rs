@@ -0,0 +1,60 @@
+use plan::Step;
+use serde_json;
+use std::{
+    fmt,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering}
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// What happened during a run, printed as one JSON object per line with
+/// --message-format json. The kind is in the `reason` field, like cargo does.
+#[derive(Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    CrateCopied {
+        package_id: &'a str,
+        source: &'a Path,
+        copy: &'a Path
+    },
+    ManifestRewritten {
+        package_id: &'a str,
+        manifest: &'a Path
+    },
+    Skipped {
+        package_id: &'a str,
+        reason: String
+    },
+    /// What --dry-run would do
+    Plan {
+        steps: &'a [Step]
+    },
+    Error {
+        code: Option<String>,
+        message: &'a str
+    }
+}
+
+/// Print events from now on
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+/// Print a line meant for people. Once events are enabled it goes to stderr,
+/// so stdout only has JSON.
+pub fn say<T: fmt::Display>(line: T) {
+    if enabled() {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+/// Print an event on stdout, if they're enabled
+pub fn emit(event: &Event) {
+    if enabled() {
+        println!("{}", serde_json::to_string(event).expect("Events are always serializable"));
+    }
+}
@@ -0,0 +1,69 @@
+use cargo::{
+    CargoResult,
+    core::Workspace,
+    util::config::Config
+};
+use copy;
+use errors::{self, Code};
+use git2::{IndexAddOption, Repository, Signature};
+use graph::{self, Features};
+use std::{
+    fs,
+    path::{Path, PathBuf}
+};
+use vendor;
+
+/// Copy the dependency called `name` to `basedir/<name>`, and commit it to a
+/// new git repository there, so edits to it are easy to follow. Returns the
+/// directory. Fails if there's no single such crate.
+pub fn fork(
+    config: &Config,
+    workspace: &Workspace,
+    features: Option<&Features>,
+    basedir: &Path,
+    name: &str
+) -> CargoResult<PathBuf> {
+    let dest = basedir.join(name);
+    if dest.join(".git").exists() {
+        config.shell().status("Using", format!("the existing fork in {}", dest.display()))?;
+        return Ok(dest);
+    }
+
+    let graph = graph::resolve(workspace, features)?;
+    let matching: Vec<&graph::Node> = graph.nodes.iter().filter(|node| node.name == name).collect();
+    let node = match matching.len() {
+        1 => matching[0],
+        0 => return Err(errors::error(Code::ReplacementNotFound, format!("Nothing in the dependency tree is called {:?}", name))),
+        _ => {
+            let versions: Vec<String> = matching.iter().map(|node| node.version.to_string()).collect();
+            return Err(errors::error(Code::AmbiguousCrate, format!(
+                "The dependency tree has {} in versions {}",
+                name, versions.join(", ")
+            )));
+        }
+    };
+    if dest.exists() {
+        return Err(errors::error(Code::CopyExists, format!("{} exists, but isn't a git repository", dest.display())));
+    }
+
+    let source = match vendor::vendored_path(config, node)? {
+        Some(path) => path,
+        None => node.root().to_path_buf()
+    };
+    config.shell().status("Forking", format!("{} {} to {}", node.name, node.version, dest.display()))?;
+    fs::create_dir_all(basedir)?;
+    copy::package(&source, &dest, &[])?;
+
+    let repo = Repository::init(&dest)?;
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let signature = match repo.signature() {
+        Ok(signature) => signature,
+        Err(_) => Signature::now("cargo-patch", "cargo-patch@localhost")?
+    };
+    let message = format!("{} {} as published", node.name, node.version);
+    repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &[])?;
+    Ok(dest)
+}
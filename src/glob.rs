@@ -0,0 +1,41 @@
+/// Match a crate name against a pattern, where `*` stands for any number of
+/// characters and `?` for exactly one
+pub fn matches(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((&'*', rest)) => (0..=name.len()).any(|skipped| matches(rest, &name[skipped..])),
+            Some((&'?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..])
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        assert!(matches("foo", "foo"));
+        assert!(!matches("foo", "foobar"));
+        assert!(!matches("foo", "fo"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("tokio-*", "tokio-io"));
+        assert!(matches("tokio-*", "tokio-"));
+        assert!(!matches("tokio-*", "tokio"));
+        assert!(matches("*", ""));
+        assert!(matches("*-sys", "openssl-sys"));
+        assert!(matches("a*b*c", "aXXbYc"));
+        assert!(!matches("a*b*c", "aXXcYb"));
+        assert!(matches("?oo", "foo"));
+        assert!(!matches("?oo", "oo"));
+        assert!(matches("f?o*", "fooo"));
+    }
+}
@@ -0,0 +1,363 @@
+use cargo::{
+    CargoResult,
+    core::{PackageId, PackageIdSpec, PackageSet, Resolve, SourceId, Workspace},
+    core::dependency::Kind
+};
+use hash::hash_file;
+use semver::{Version, VersionReq};
+use serde_json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf}
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SourceKind {
+    Path,
+    Git,
+    Registry,
+    Other
+}
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Source {
+    pub kind: SourceKind,
+    pub url: String,
+    /// The whole source ID as a URL, with its kind and reference
+    pub id: String,
+    pub default_registry: bool
+}
+impl Source {
+    fn new(id: &SourceId) -> Self {
+        let kind = if id.is_path() {
+            SourceKind::Path
+        } else if id.is_git() {
+            SourceKind::Git
+        } else if id.is_registry() {
+            SourceKind::Registry
+        } else {
+            SourceKind::Other
+        };
+        Self {
+            kind,
+            url: id.url().to_string(),
+            id: id.to_url().to_string(),
+            default_registry: id.is_default_registry()
+        }
+    }
+}
+
+/// A dependency as declared in a package's manifest
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Dependency {
+    pub name: String,
+    pub req: VersionReq,
+    pub kind: String,
+    /// The platform of the `[target.<cfg>]` table it's declared in, if any
+    pub target: Option<String>,
+    pub optional: bool
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Node {
+    /// The package ID, formatted like cargo does
+    pub id: String,
+    pub name: String,
+    pub version: Version,
+    pub source: Source,
+    pub manifest_path: PathBuf,
+    pub dependencies: Vec<Dependency>,
+    /// The indices of the resolved dependencies
+    pub deps: Vec<usize>
+}
+impl Node {
+    pub fn root(&self) -> &Path {
+        self.manifest_path.parent().expect("Manifest path didn't have parent")
+    }
+    /// The name of this package's copy, unique even if there are
+    /// multiple versions of the same crate
+    pub fn dir_name(&self) -> String {
+        format!("{}-{}", self.name, self.version)
+    }
+}
+
+/// The features to resolve with, like cargo's flags of the same name
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Features {
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool
+}
+
+/// The resolved dependency graph, detached from cargo's types
+/// so it can be cached between runs
+#[derive(Serialize, Deserialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    /// Hashes of the files that went into the resolution, empty for
+    /// configuration files that didn't exist
+    inputs: BTreeMap<PathBuf, String>,
+    /// The features it was resolved with, or None if they all were
+    #[serde(default)]
+    features: Option<Features>,
+    /// Whether it was resolved with --offline and with --locked or --frozen
+    #[serde(default)]
+    offline: bool,
+    #[serde(default)]
+    locked: bool,
+
+    #[serde(skip)]
+    index: HashMap<String, usize>
+}
+impl Graph {
+    pub fn new(packages: &PackageSet, resolve: &Resolve) -> CargoResult<Self> {
+        let ids: Vec<&PackageId> = resolve.iter().collect();
+        let index: HashMap<String, usize> = ids.iter()
+            .enumerate()
+            .map(|(i, id)| (id.to_string(), i))
+            .collect();
+
+        let mut nodes = Vec::with_capacity(ids.len());
+        for id in &ids {
+            let package = packages.get(id)?;
+            nodes.push(Node {
+                id: id.to_string(),
+                name: package.name().to_string(),
+                version: package.version().clone(),
+                source: Source::new(id.source_id()),
+                manifest_path: package.manifest_path().to_path_buf(),
+                dependencies: package.dependencies().iter()
+                    .map(|dep| Dependency {
+                        name: dep.name().to_string(),
+                        req: dep.version_req().clone(),
+                        kind: match dep.kind() {
+                            Kind::Normal => "normal",
+                            Kind::Development => "dev",
+                            Kind::Build => "build"
+                        }.to_string(),
+                        target: dep.platform().map(|platform| platform.to_string()),
+                        optional: dep.is_optional()
+                    })
+                    .collect(),
+                deps: resolve.deps(id).map(|dep| index[&dep.to_string()]).collect()
+            });
+        }
+
+        Ok(Self {
+            nodes,
+            inputs: BTreeMap::new(),
+            features: None,
+            offline: false,
+            locked: false,
+            index
+        })
+    }
+    fn reindex(&mut self) {
+        self.index = self.nodes.iter()
+            .enumerate()
+            .map(|(i, node)| (node.id.clone(), i))
+            .collect();
+    }
+
+    pub fn find(&self, id: &PackageId) -> Option<usize> {
+        self.index.get(&id.to_string()).cloned()
+    }
+    pub fn deps<'a>(&'a self, node: &'a Node) -> impl Iterator<Item = &'a Node> + 'a {
+        node.deps.iter().map(move |&i| &self.nodes[i])
+    }
+
+    /// Find every package reachable from the `roots` that depends on a target,
+    /// directly or transitively. Targets are replaced, so nothing past them
+    /// is looked at. Each node and edge is only visited once.
+    pub fn dependents<F>(&self, roots: &[usize], is_target: F) -> Vec<usize>
+        where F: Fn(&Node) -> bool
+    {
+        self.dependents_except(roots, is_target, |_| false, |_, _| true)
+    }
+    /// Like `dependents`, but leaving out the packages matching `is_excluded`,
+    /// which also don't make their own dependents dirty, and only following
+    /// the dependencies `follows` accepts
+    pub fn dependents_except<F, G, H>(&self, roots: &[usize], is_target: F, is_excluded: G, follows: H) -> Vec<usize>
+        where F: Fn(&Node) -> bool,
+              G: Fn(&Node) -> bool,
+              H: Fn(&Node, &Node) -> bool
+    {
+        let target: Vec<bool> = self.nodes.iter().map(|node| is_target(node)).collect();
+
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = roots.to_vec();
+        for &root in roots {
+            reachable[root] = true;
+        }
+        while let Some(i) = stack.pop() {
+            if target[i] && !roots.contains(&i) {
+                continue;
+            }
+            let node = &self.nodes[i];
+            for &dep in &node.deps {
+                if !reachable[dep] && follows(node, &self.nodes[dep]) {
+                    reachable[dep] = true;
+                    stack.push(dep);
+                }
+            }
+        }
+
+        let mut reverse = vec![Vec::new(); self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if reachable[i] && !target[i] {
+                for &dep in node.deps.iter().filter(|&&dep| follows(node, &self.nodes[dep])) {
+                    reverse[dep].push(i);
+                }
+            }
+        }
+
+        let mut dirty = vec![false; self.nodes.len()];
+        let mut stack: Vec<usize> = (0..self.nodes.len()).filter(|&i| reachable[i] && target[i]).collect();
+        while let Some(i) = stack.pop() {
+            for &dependent in &reverse[i] {
+                if !dirty[dependent] && !is_excluded(&self.nodes[dependent]) {
+                    dirty[dependent] = true;
+                    stack.push(dependent);
+                }
+            }
+        }
+
+        (0..self.nodes.len()).filter(|&i| dirty[i]).collect()
+    }
+
+    /// Load the graph cached by a previous run, if it was resolved with the
+    /// same features and flags, none of its inputs changed and every crate
+    /// it found is still there
+    pub fn load_cached(workspace: &Workspace, features: Option<&Features>) -> CargoResult<Option<Self>> {
+        let path = cache_path(workspace);
+        if !path.exists() {
+            return Ok(None);
+        }
+        // Streamed, so the file and the graph aren't both in memory at once
+        let mut graph: Self = match serde_json::from_reader(BufReader::new(File::open(&path)?)) {
+            Ok(graph) => graph,
+            // Probably written by an older version
+            Err(_) => return Ok(None)
+        };
+        let (offline, locked) = flags(workspace);
+        if graph.inputs.is_empty() || graph.features.as_ref() != features
+                || graph.offline != offline || graph.locked != locked {
+            return Ok(None);
+        }
+        for (input, hash) in &graph.inputs {
+            if hash_input(input)? != *hash {
+                return Ok(None);
+            }
+        }
+        // Registry and git sources can be removed from cargo's cache
+        if graph.nodes.iter().any(|node| !node.manifest_path.exists()) {
+            return Ok(None);
+        }
+        graph.reindex();
+        Ok(Some(graph))
+    }
+    /// Cache the graph, keyed by the hashes of the lock file, the root
+    /// manifest, every local manifest and every cargo configuration file
+    /// that could apply
+    pub fn save_cached(&mut self, workspace: &Workspace) -> CargoResult<()> {
+        let lockfile = workspace.root().join("Cargo.lock");
+        if !lockfile.exists() {
+            return Ok(());
+        }
+        let mut inputs = BTreeMap::new();
+        inputs.insert(lockfile.clone(), hash_file(&lockfile)?);
+        // The root manifest of a virtual workspace isn't a package, but its
+        // [patch], [replace] and members change the resolution all the same
+        let root_manifest = workspace.root().join("Cargo.toml");
+        inputs.insert(root_manifest.clone(), hash_file(&root_manifest)?);
+        for node in &self.nodes {
+            if node.source.kind == SourceKind::Path {
+                inputs.insert(node.manifest_path.clone(), hash_file(&node.manifest_path)?);
+            }
+        }
+        // Source replacement and [patch] can be set there, so one showing up
+        // counts as a change too
+        for config in config_paths(workspace) {
+            let hash = hash_input(&config)?;
+            inputs.insert(config, hash);
+        }
+        self.inputs = inputs;
+        let (offline, locked) = flags(workspace);
+        self.offline = offline;
+        self.locked = locked;
+
+        let path = cache_path(workspace);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+fn cache_path(workspace: &Workspace) -> PathBuf {
+    workspace.target_dir().into_path_unlocked().join("cargo-patch").join("resolve.json")
+}
+/// The cargo configuration files read for this workspace, whether they exist or not
+fn config_paths(workspace: &Workspace) -> Vec<PathBuf> {
+    let home = workspace.config().home().clone().into_path_unlocked();
+    let mut dirs = Vec::new();
+    let mut dir = Some(workspace.root());
+    while let Some(current) = dir {
+        dirs.push(current.join(".cargo"));
+        dir = current.parent();
+    }
+    dirs.push(home);
+    let mut paths = Vec::new();
+    for dir in dirs {
+        paths.push(dir.join("config"));
+        paths.push(dir.join("config.toml"));
+    }
+    paths
+}
+/// Hash a file, or return an empty hash if it doesn't exist
+fn hash_input(path: &Path) -> CargoResult<String> {
+    if path.exists() {
+        Ok(hash_file(path)?)
+    } else {
+        Ok(String::new())
+    }
+}
+/// Whether --offline was passed, and whether the lock file is locked by --locked or --frozen
+fn flags(workspace: &Workspace) -> (bool, bool) {
+    let config = workspace.config();
+    (config.cli_unstable().offline, !config.lock_update_allowed())
+}
+
+/// Resolve the workspace with the given features, or with all of them, or
+/// use the cached result of a previous run
+pub fn resolve(workspace: &Workspace, features: Option<&Features>) -> CargoResult<Graph> {
+    if let Some(graph) = Graph::load_cached(workspace, features)? {
+        return Ok(graph);
+    }
+    // Cargo's package set is dropped as soon as the graph is built
+    let mut graph = {
+        let (packages, resolve) = match features {
+            Some(features) => {
+                let specs: Vec<PackageIdSpec> = workspace.members()
+                    .map(|member| PackageIdSpec::from_package_id(member.package_id()))
+                    .collect();
+                ::cargo::ops::resolve_ws_precisely(
+                    workspace,
+                    None,
+                    &features.features,
+                    features.all_features,
+                    features.no_default_features,
+                    &specs
+                )?
+            },
+            None => ::cargo::ops::resolve_ws(workspace)?
+        };
+        Graph::new(&packages, &resolve)?
+    };
+    graph.features = features.cloned();
+    graph.save_cached(workspace)?;
+    Ok(graph)
+}
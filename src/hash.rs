@@ -0,0 +1,59 @@
+use cargo::util::{to_hex, Sha256};
+use ignore;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io,
+    path::Path
+};
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(&fs::read(path)?);
+    Ok(to_hex(&hasher.finish()))
+}
+/// Hash the relative paths and contents of every file in a directory
+pub fn hash_tree(path: &Path) -> io::Result<String> {
+    fn visit(root: &Path, path: &Path, hasher: &mut Sha256) -> io::Result<()> {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.sort();
+            for entry in entries {
+                visit(root, &entry, hasher)?;
+            }
+        } else {
+            let relative = path.strip_prefix(root).unwrap();
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update(&[0]);
+            hasher.update(&fs::read(path)?);
+        }
+        Ok(())
+    }
+    let mut hasher = Sha256::new();
+    visit(path, path, &mut hasher)?;
+    Ok(to_hex(&hasher.finish()))
+}
+/// Hash every file in a directory separately, keyed by relative path. The
+/// files left out of copies by the built-in rules or the globs in `patterns`
+/// are skipped without being read, like build output and `.git`.
+pub fn hash_files(path: &Path, patterns: &[String]) -> io::Result<BTreeMap<String, String>> {
+    fn visit(root: &Path, path: &Path, patterns: &[String], hashes: &mut BTreeMap<String, String>) -> io::Result<()> {
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                let path = entry?.path();
+                if !ignore::is_ignored(path.strip_prefix(root).unwrap(), patterns) {
+                    visit(root, &path, patterns, hashes)?;
+                }
+            }
+        } else {
+            let relative = path.strip_prefix(root).unwrap();
+            hashes.insert(relative.to_string_lossy().into_owned(), hash_file(path)?);
+        }
+        Ok(())
+    }
+    let mut hashes = BTreeMap::new();
+    visit(path, path, patterns, &mut hashes)?;
+    Ok(hashes)
+}
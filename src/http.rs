@@ -0,0 +1,22 @@
+use cargo::{CargoResult, ops, util::Config};
+
+/// Download a file with cargo's HTTP settings, or return None if the server says it's not there
+pub fn get(config: &Config, url: &str) -> CargoResult<Option<Vec<u8>>> {
+    let mut handle = ops::http_handle(config)?;
+    handle.get(true)?;
+    handle.url(url)?;
+    handle.follow_location(true)?;
+    let mut body = Vec::new();
+    {
+        let mut transfer = handle.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    if handle.response_code()? != 200 {
+        return Ok(None);
+    }
+    Ok(Some(body))
+}
@@ -0,0 +1,30 @@
+use glob;
+use std::path::{Component, Path};
+
+/// The histories of version control systems, wherever they are in a package
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn", ".jj"];
+/// Build output, at the top of a package
+const TOP_LEVEL: &[&str] = &["target"];
+
+/// Whether the file or directory at `relative`, a path inside a package, is
+/// left out of copies. Besides the built-in rules, it's left out if one of the
+/// globs in `patterns` matches the path, like `benches/data/*`, or the name of
+/// any part of it, like `*.log`. Globs starting with `/` only match the path.
+pub fn is_ignored(relative: &Path, patterns: &[String]) -> bool {
+    let names: Vec<String> = relative.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None
+        })
+        .collect();
+    if names.first().map(|name| TOP_LEVEL.contains(&name.as_str())).unwrap_or(false)
+        || names.iter().any(|name| VCS_DIRS.contains(&name.as_str())) {
+        return true;
+    }
+    let path = names.join("/");
+    patterns.iter().any(|pattern| {
+        let anchored = pattern.starts_with('/');
+        let pattern = pattern.trim_matches('/');
+        glob::matches(pattern, &path) || (!anchored && names.iter().any(|name| glob::matches(pattern, name)))
+    })
+}
@@ -0,0 +1,70 @@
+use events;
+use graph::Graph;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io,
+    path::Path
+};
+
+/// Count the lines of Rust in a package, as a rough measure of how long it takes to build
+fn lines(dir: &Path) -> io::Result<usize> {
+    let mut lines = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if !path.ends_with("target") && !path.ends_with(".git") {
+                lines += self::lines(&path)?;
+            }
+        } else if path.extension().map(|ext| ext == "rs").unwrap_or(false) {
+            lines += fs::read_to_string(&path).map(|contents| contents.lines().count()).unwrap_or(0);
+        }
+    }
+    Ok(lines)
+}
+
+/// List the crates that are built more than once, and estimate how much
+/// extra work that is compared to building each only once
+pub fn print(graph: &Graph, basedir: &Path) -> io::Result<()> {
+    let mut sizes = Vec::with_capacity(graph.nodes.len());
+    for node in &graph.nodes {
+        sizes.push(lines(node.root())?);
+    }
+    let total: usize = sizes.iter().sum();
+
+    let mut by_name: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, node) in graph.nodes.iter().enumerate() {
+        by_name.entry(&node.name).or_insert_with(Vec::new).push(i);
+    }
+    let mut rows = Vec::new();
+    let mut extra = 0;
+    for (name, nodes) in by_name {
+        if nodes.len() < 2 {
+            continue;
+        }
+        // One build is needed anyway, the rest is extra
+        let largest = nodes.iter().map(|&i| sizes[i]).max().unwrap_or(0);
+        let lines = nodes.iter().map(|&i| sizes[i]).sum::<usize>() - largest;
+        let copies = nodes.iter().filter(|&&i| graph.nodes[i].manifest_path.starts_with(basedir)).count();
+        extra += lines;
+        rows.push((name, nodes.len(), copies, lines));
+    }
+
+    if rows.is_empty() {
+        events::say("Every crate is built only once");
+        return Ok(());
+    }
+    let width = rows.iter().map(|row| row.0.len()).chain(Some("CRATE".len())).max().unwrap_or(0);
+    events::say(format!("{:w$}  {:>6}  {:>6}  {:>11}", "CRATE", "BUILDS", "COPIES", "EXTRA LINES", w = width));
+    for (name, builds, copies, lines) in &rows {
+        events::say(format!("{:w$}  {:>6}  {:>6}  {:>11}", name, builds, copies, lines, w = width));
+    }
+    let without = total - extra;
+    events::say(format!(
+        "About {} extra lines of Rust to build, {:.0}% more than if each crate was built once",
+        extra,
+        if without == 0 { 0.0 } else { extra as f64 * 100.0 / without as f64 }
+    ));
+    events::say("Replacing with a [patch] section in the root manifest instead builds every crate once");
+    Ok(())
+}
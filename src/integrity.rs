@@ -0,0 +1,109 @@
+use cargo::{CargoResult, core::Shell};
+use diff::GENERATED;
+use hash::{hash_file, hash_files};
+use state::State;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf}
+};
+
+/// Compare all copies against their recorded hashes, optionally restoring
+/// changed or missing files from the original source. Returns false if
+/// anything didn't match.
+pub fn verify(shell: &mut Shell, basedir: &Path, restore: bool) -> CargoResult<bool> {
+    let state = State::load(basedir)?;
+    let mut intact = true;
+
+    for (name, copy) in &state.copies {
+        let dest = basedir.join(name);
+        let current = if dest.exists() { hash_files(&dest, &state.ignored(name))? } else { BTreeMap::new() };
+
+        let mut damaged = Vec::new();
+        for (file, hash) in &copy.files {
+            match current.get(file) {
+                None => damaged.push((file, "missing")),
+                Some(current) if current != hash => damaged.push((file, "modified")),
+                Some(_) => ()
+            }
+        }
+        for file in current.keys() {
+            if !copy.files.contains_key(file) {
+                shell.warn(format!("{}: unexpected file {}", name, file))?;
+                intact = false;
+            }
+        }
+        for (file, problem) in damaged {
+            intact = false;
+            shell.warn(format!("{}: {} {}", name, problem, file))?;
+            if !restore {
+                continue;
+            }
+            if file == "Cargo.toml" {
+                shell.warn(format!("{}: can't restore the rewritten Cargo.toml, remove the copy and run cargo patch again", name))?;
+                continue;
+            }
+            let original = PathBuf::from(&copy.source).join(file);
+            let target = dest.join(file);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&original, &target)?;
+            shell.status("Restored", format!("{} in {}", file, name))?;
+        }
+    }
+
+    if intact {
+        shell.status("Verified", format!("all {} copies are intact", state.copies.len()))?;
+    }
+    Ok(intact)
+}
+
+/// Check that the sources of the copies haven't changed since they were
+/// copied, and that the manifests rewritten in place are still as
+/// cargo-patch left them. Returns false if anything drifted.
+pub fn drift(shell: &mut Shell, basedir: &Path) -> CargoResult<bool> {
+    let state = State::load(basedir)?;
+    let mut current = true;
+    // The manifests of copies are rewritten, and the generated files aren't always copied
+    let compared = |hashes: &mut BTreeMap<String, String>| {
+        hashes.remove("Cargo.toml");
+        for file in GENERATED {
+            hashes.remove(*file);
+        }
+    };
+
+    for (name, copy) in &state.copies {
+        let source = Path::new(&copy.source);
+        if !source.exists() {
+            shell.warn(format!("{}: the source {} is gone", name, source.display()))?;
+            current = false;
+            continue;
+        }
+        // Left out the same way as when the copy was made, including the
+        // files cargo wouldn't package for --packaged-only copies
+        let mut upstream = hash_files(source, &state.ignored(name))?;
+        let mut recorded = copy.files.clone();
+        compared(&mut upstream);
+        compared(&mut recorded);
+        if upstream != recorded {
+            shell.warn(format!("{}: {} changed since it was copied, see `cargo patch update`", name, source.display()))?;
+            current = false;
+        }
+    }
+    for (manifest, hash) in &state.rewritten {
+        let path = Path::new(manifest);
+        if !path.exists() {
+            shell.warn(format!("{} is gone", manifest))?;
+            current = false;
+        } else if hash_file(path)? != *hash {
+            shell.warn(format!("{} was changed since cargo-patch rewrote it", manifest))?;
+            current = false;
+        }
+    }
+
+    if current {
+        shell.status("Verified", "no sources or manifests drifted")?;
+    }
+    Ok(current)
+}
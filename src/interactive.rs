@@ -0,0 +1,74 @@
+use cli::Replace;
+use events;
+use graph::Graph;
+use selector::Selector;
+use state::State;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::Path
+};
+use tree;
+
+fn ask(question: &str) -> io::Result<String> {
+    eprint!("{} ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// Show the dependency trees of `roots`, and ask which crates to replace,
+/// and with which git URL or local path
+pub fn choose(graph: &Graph, roots: &[usize], state: &State, basedir: &Path) -> io::Result<Vec<Replace>> {
+    for &root in roots {
+        tree::print(graph, root, state, basedir, true);
+    }
+
+    let mut versions: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (i, node) in graph.nodes.iter().enumerate() {
+        if !roots.contains(&i) {
+            versions.entry(&node.name).or_insert_with(Vec::new).push(node.version.to_string());
+        }
+    }
+    let names: Vec<&str> = versions.keys().cloned().collect();
+    events::say("");
+    for (i, name) in names.iter().enumerate() {
+        events::say(format!("{:>4}. {} {}", i + 1, name, versions[name].join(", ")));
+    }
+
+    let chosen = loop {
+        let answer = ask("Replace which crates? (numbers or names, separated by spaces)")?;
+        let mut chosen = Vec::new();
+        let mut unknown = Vec::new();
+        for word in answer.split_whitespace() {
+            let name = match word.parse::<usize>() {
+                Ok(i) if i >= 1 && i <= names.len() => Some(names[i - 1]),
+                _ => names.iter().cloned().find(|name| *name == word)
+            };
+            match name {
+                Some(name) if !chosen.contains(&name) => chosen.push(name),
+                Some(_) => (),
+                None => unknown.push(word)
+            }
+        }
+        if unknown.is_empty() {
+            break chosen;
+        }
+        events::say(format!("Not in the dependency tree: {}", unknown.join(", ")));
+    };
+
+    let mut replace = Vec::with_capacity(chosen.len());
+    for name in chosen {
+        let url = ask(&format!("Git URL or local path for {} (empty to skip):", name))?;
+        if !url.is_empty() {
+            replace.push(Replace {
+                name: name.to_string(),
+                selector: Selector::default(),
+                target: None,
+                url
+            });
+        }
+    }
+    Ok(replace)
+}
@@ -0,0 +1,80 @@
+use cargo::CargoResult;
+use ctrlc;
+use std::{
+    fs,
+    io,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicBool, Ordering}
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Catch Ctrl-C so that the current operation can be rolled back. Pressing
+/// it again exits right away, for when a step like resolving hangs, and
+/// leaves the journal for --resume.
+pub fn install() -> CargoResult<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again, exiting without rolling back. Continue with `cargo patch --resume`");
+            process::exit(130);
+        }
+    })?;
+    Ok(())
+}
+/// Returns an error if Ctrl-C has been pressed
+pub fn check() -> io::Result<()> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted by user"))
+    } else {
+        Ok(())
+    }
+}
+/// Whether Ctrl-C has been pressed
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Everything written during this run, so it can be undone
+#[derive(Default)]
+pub struct Rollback {
+    copied: Vec<PathBuf>,
+    manifests: Vec<(PathBuf, String)>,
+    created: Vec<PathBuf>
+}
+impl Rollback {
+    /// Remember a directory that was copied in this run
+    pub fn copied(&mut self, path: PathBuf) {
+        self.copied.push(path);
+    }
+    /// Remember the contents of a manifest before it was rewritten
+    pub fn manifest(&mut self, path: PathBuf, original: String) {
+        self.manifests.push((path, original));
+    }
+    /// Remember a file that didn't exist, and may be written in this run
+    pub fn created(&mut self, path: PathBuf) {
+        self.created.push(path);
+    }
+    /// Restore all rewritten manifests and remove all copies and created files
+    pub fn run(self) {
+        for path in self.created.into_iter().rev() {
+            if path.exists() {
+                if let Err(err) = fs::remove_file(&path) {
+                    eprintln!("Failed to remove {}: {}", path.display(), err);
+                }
+            }
+        }
+        for (path, original) in self.manifests.into_iter().rev() {
+            if let Err(err) = fs::write(&path, original) {
+                eprintln!("Failed to restore {}: {}", path.display(), err);
+            }
+        }
+        for path in self.copied.into_iter().rev() {
+            if path.exists() {
+                if let Err(err) = fs::remove_dir_all(&path) {
+                    eprintln!("Failed to remove {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+}
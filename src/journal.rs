@@ -0,0 +1,92 @@
+use cargo::CargoResult;
+use hash::{hash_file, hash_tree};
+use selector::Selector;
+use state::Annotation;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io,
+    path::{Path, PathBuf}
+};
+use toml;
+
+pub const FILE: &str = ".journal.toml";
+
+/// A record of the work done so far, deleted once a run completes.
+/// If it's still around, the previous run was interrupted.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Journal {
+    pub replace: BTreeMap<String, String>,
+    /// Replacements restricted to a target, and its cfg
+    #[serde(default)]
+    pub targets: BTreeMap<String, String>,
+    /// Replacements restricted to some of the crates with their name
+    #[serde(default)]
+    pub selectors: BTreeMap<String, Selector>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, Annotation>,
+    /// Copied directories, and the hash of their contents
+    #[serde(default)]
+    pub copies: BTreeMap<String, String>,
+    /// Rewritten manifests, and the hash of their contents after the rewrite
+    #[serde(default)]
+    pub manifests: BTreeMap<String, String>,
+
+    #[serde(skip)]
+    path: PathBuf
+}
+impl Journal {
+    pub fn new(basedir: &Path, replace: BTreeMap<String, String>) -> Self {
+        Self {
+            replace,
+            path: basedir.join(FILE),
+            ..Default::default()
+        }
+    }
+    /// Load the journal of an interrupted run, if any
+    pub fn load(basedir: &Path) -> CargoResult<Option<Self>> {
+        let path = basedir.join(FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut journal: Self = toml::from_str(&fs::read_to_string(&path)?)?;
+        journal.path = path;
+        Ok(Some(journal))
+    }
+    pub fn save(&self) -> CargoResult<()> {
+        fs::write(&self.path, toml::to_string(self)?)?;
+        Ok(())
+    }
+    /// Delete the journal, marking the run as complete
+    pub fn finish(self) -> io::Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if `path` was copied in a previous run and hasn't changed since
+    pub fn is_copied(&self, path: &Path) -> io::Result<bool> {
+        match self.copies.get(&*path.to_string_lossy()) {
+            Some(hash) => Ok(path.exists() && hash_tree(path)? == *hash),
+            None => Ok(false)
+        }
+    }
+    pub fn record_copy(&mut self, path: &Path) -> CargoResult<()> {
+        let hash = hash_tree(path)?;
+        self.copies.insert(path.to_string_lossy().into_owned(), hash);
+        self.save()
+    }
+    /// Returns true if `path` was rewritten in a previous run and hasn't changed since
+    pub fn is_rewritten(&self, path: &Path) -> io::Result<bool> {
+        match self.manifests.get(&*path.to_string_lossy()) {
+            Some(hash) => Ok(path.exists() && hash_file(path)? == *hash),
+            None => Ok(false)
+        }
+    }
+    pub fn record_rewrite(&mut self, path: &Path) -> CargoResult<()> {
+        let hash = hash_file(path)?;
+        self.manifests.insert(path.to_string_lossy().into_owned(), hash);
+        self.save()
+    }
+}
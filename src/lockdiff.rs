@@ -0,0 +1,110 @@
+use cargo::{CargoResult, core::Workspace, ops};
+use events;
+use graph::{Graph, SourceKind};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs
+};
+use toml;
+use urls;
+
+/// A package, as far as the comparison is concerned
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Entry {
+    version: String,
+    /// The canonical source URL, or empty for local packages
+    source: String
+}
+
+/// Strip the kind prefix, query and precise revision off a lock file source
+fn lock_source(source: &str) -> String {
+    let source = source.splitn(2, '+').nth(1).unwrap_or(source);
+    let source = source.split('#').next().unwrap_or(source);
+    let source = source.split('?').next().unwrap_or(source);
+    urls::canonicalize(source)
+}
+fn describe(entry: &Entry) -> String {
+    if entry.source.is_empty() {
+        format!("{} (local)", entry.version)
+    } else {
+        format!("{} ({})", entry.version, entry.source)
+    }
+}
+
+/// Compare the packages in the lock file against a fresh resolution of the
+/// (patched) manifests. The lock file itself is left as it was.
+pub fn print(workspace: &Workspace) -> CargoResult<()> {
+    let lockfile = workspace.root().join("Cargo.lock");
+    let original = fs::read_to_string(&lockfile)?;
+
+    let mut old: BTreeMap<String, BTreeSet<Entry>> = BTreeMap::new();
+    let parsed: toml::Value = toml::from_str(&original)?;
+    let packages = parsed.get("package").and_then(|packages| packages.as_array()).cloned().unwrap_or_default();
+    for package in &packages {
+        let name = package.get("name").and_then(|name| name.as_str());
+        let version = package.get("version").and_then(|version| version.as_str());
+        if let (Some(name), Some(version)) = (name, version) {
+            old.entry(name.to_string()).or_insert_with(BTreeSet::new).insert(Entry {
+                version: version.to_string(),
+                source: package.get("source").and_then(|source| source.as_str()).map(lock_source).unwrap_or_default()
+            });
+        }
+    }
+
+    // Resolving writes the lock file, so put back the original afterwards
+    let resolved = ops::resolve_ws(workspace);
+    if fs::read_to_string(&lockfile)? != original {
+        fs::write(&lockfile, &original)?;
+    }
+    let (packages, resolve) = resolved?;
+    let graph = Graph::new(&packages, &resolve)?;
+
+    let mut new: BTreeMap<String, BTreeSet<Entry>> = BTreeMap::new();
+    for node in &graph.nodes {
+        new.entry(node.name.clone()).or_insert_with(BTreeSet::new).insert(Entry {
+            version: node.version.to_string(),
+            source: if node.source.kind == SourceKind::Path { String::new() } else { urls::canonicalize(&node.source.url) }
+        });
+    }
+
+    let mut changed = false;
+    for (name, after) in &new {
+        let before = match old.get(name) {
+            Some(before) => before,
+            None => {
+                changed = true;
+                for entry in after {
+                    events::say(format!("+ {} {} (new dependency)", name, describe(entry)));
+                }
+                continue;
+            }
+        };
+        if before == after {
+            continue;
+        }
+        changed = true;
+        for entry in before.difference(after) {
+            events::say(format!("- {} {}", name, describe(entry)));
+        }
+        for entry in after.difference(before) {
+            events::say(format!("+ {} {}", name, describe(entry)));
+        }
+        if after.len() > before.len() {
+            events::say(format!("  {} is now duplicated ({} versions)", name, after.len()));
+        } else if after.len() < before.len() {
+            events::say(format!("  {} is now unified ({} versions instead of {})", name, after.len(), before.len()));
+        }
+    }
+    for (name, before) in &old {
+        if !new.contains_key(name) {
+            changed = true;
+            for entry in before {
+                events::say(format!("- {} {} (no longer used)", name, describe(entry)));
+            }
+        }
+    }
+    if !changed {
+        events::say("The resolution is the same as in Cargo.lock");
+    }
+    Ok(())
+}
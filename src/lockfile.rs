@@ -0,0 +1,55 @@
+use cargo::{CargoResult, ops, util::Config};
+use manifest;
+use std::{fs, path::Path};
+use toml_edit::Document;
+
+/// Resolve the patched workspace again, which rewrites its lock file to point
+/// to the replacements. If it can't be resolved, the checksums of the replaced
+/// crates are stripped from the lock file instead, since those belong to the
+/// old sources. Returns false if it had to fall back to that.
+pub fn update(config: &Config, manifest: &Path, replaced: &[&str]) -> CargoResult<bool> {
+    let lockfile = manifest.with_file_name("Cargo.lock");
+    let resolved = manifest::workspace(manifest, config).and_then(|workspace| ops::resolve_ws(&workspace).map(|_| ()));
+    if resolved.is_ok() || !lockfile.exists() {
+        return resolved.map(|()| true);
+    }
+    let contents = fs::read_to_string(&lockfile)?;
+    fs::write(&lockfile, strip_checksums(&contents, replaced)?)?;
+    Ok(false)
+}
+
+/// Remove the checksums of these crates, both from their `[[package]]`
+/// entries and from the `[metadata]` table older lock files keep them in
+pub fn strip_checksums(contents: &str, replaced: &[&str]) -> CargoResult<String> {
+    let mut document: Document = contents.parse()?;
+    {
+        let root = document.as_table_mut();
+        let packages = if root.contains_key("package") { root.entry("package").as_array_of_tables_mut() } else { None };
+        if let Some(packages) = packages {
+            for i in 0..packages.len() {
+                if let Some(package) = packages.get_mut(i) {
+                    let name = package.get("name").and_then(|name| name.as_str()).map(String::from);
+                    if name.map(|name| replaced.contains(&&*name)).unwrap_or(false) {
+                        package.remove("checksum");
+                    }
+                }
+            }
+        }
+        let metadata = if root.contains_key("metadata") { root.entry("metadata").as_table_mut() } else { None };
+        if let Some(metadata) = metadata {
+            // Keys look like `checksum serde 1.0.0 (registry+https://...)`
+            let stale: Vec<String> = metadata.iter()
+                .map(|(key, _)| key)
+                .filter(|key| {
+                    let mut words = key.split(' ');
+                    words.next() == Some("checksum") && words.next().map(|name| replaced.contains(&name)).unwrap_or(false)
+                })
+                .map(String::from)
+                .collect();
+            for key in stale {
+                metadata.remove(&key);
+            }
+        }
+    }
+    Ok(document.to_string())
+}
@@ -1,240 +1,1507 @@
-#[macro_use] extern crate clap;
+extern crate atty;
 extern crate cargo;
+extern crate ctrlc;
+extern crate failure;
+extern crate git2;
+extern crate rayon;
+extern crate serde;
+extern crate serde_json;
+#[macro_use] extern crate serde_derive;
+extern crate semver;
+#[macro_use] extern crate structopt;
 extern crate toml;
+extern crate toml_edit;
+
+mod adopt;
+mod build;
+mod cli;
+mod command;
+mod completions;
+mod copy;
+mod dates;
+mod deny;
+mod diff;
+mod errors;
+mod events;
+mod fork;
+mod glob;
+mod graph;
+mod hash;
+mod http;
+mod ignore;
+mod impact;
+mod integrity;
+mod interactive;
+mod interrupt;
+mod journal;
+mod lockdiff;
+mod lockfile;
+mod manifest;
+mod overrides;
+mod packaged;
+mod patches;
+mod patchfile;
+mod plan;
+mod pulls;
+mod refresh;
+mod retry;
+mod sandbox;
+mod script;
+mod selector;
+mod send;
+mod shadow;
+mod skip;
+mod state;
+mod status;
+mod sync;
+mod tarball;
+mod tree;
+mod unpatch;
+mod urls;
+mod vcs;
+mod vendor;
+mod versions;
 
 use cargo::{
     CargoResult,
-    core::{Package, PackageId, Workspace},
-    ops,
+    core::{Package, Shell, Workspace, shell::Verbosity},
     util::{config::Config, important_paths}
 };
-use clap::{App as Clap, Arg, SubCommand};
+use cli::{Cargo, Command, MessageFormat, Mode, OnConflict, Relax};
+use errors::Code;
+use events::Event;
+use failure::ResultExt;
+use graph::{Node, SourceKind};
+use manifest::PackagePath;
+use patchfile::PatchFile;
+use plan::Plan;
+use rayon::prelude::*;
+use refresh::Staleness;
+use sandbox::Sandbox;
+use semver::Version;
+use structopt::StructOpt;
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{HashMap, HashSet},
     env,
-    fmt,
     fs,
-    io,
-    path::{Path, PathBuf}
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process
 };
 
-enum PackagePath<'a> {
-    Git(&'a str),
-    Path(PathBuf)
-}
-struct StackEntry<'a, I>
-    where I: Iterator<Item = &'a PackageId>
-{
-    package: &'a Package,
-    dependencies: I,
-    updated: Option<HashMap<String, PackagePath<'a>>>
-}
-impl<'a, I> fmt::Debug for StackEntry<'a, I>
-    where I: Iterator<Item = &'a PackageId>
-{
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.package)
+
+/// The exit status when the run fails, the same as cargo's
+const FAILURE: i32 = 101;
+
+fn main() {
+    if let Err(err) = run() {
+        errors::report(&err);
+        process::exit(FAILURE);
     }
 }
 
-fn main() -> CargoResult<()> {
-    let matches = Clap::new(crate_name!())
-        .about(crate_description!())
-        .author(crate_authors!())
-        .version(crate_version!())
-        .subcommand(SubCommand::with_name("patch")
-            .about(crate_description!())
-            .author(crate_authors!())
-            .version(crate_version!())
-            .arg(Arg::with_name("replace")
-                .long("replace")
-                .takes_value(true)
-                .multiple(true)))
-        .get_matches();
-
-    if matches.subcommand_name() != Some("patch") {
-        eprintln!("Don't run the binary directly, run it with cargo:");
-        eprintln!("cargo patch");
+fn run() -> CargoResult<()> {
+    let Cargo::Patch(mut args) = Cargo::from_args();
+    if args.message_format == MessageFormat::Json {
+        events::enable();
+    }
+
+    // Completions are needed outside of projects too
+    if let Some(Command::Completions { shell: Some(shell), crate_names: false }) = args.command {
+        completions::print(shell);
         return Ok(());
     }
-    let matches = matches.subcommand_matches("patch").expect("Subcommand is patch but no matches for patch");
 
-    let mut replace = HashMap::with_capacity(16);
+    let cwd = env::current_dir()?;
+    let manifest = match args.manifest_path {
+        Some(ref path) => cwd.join(path),
+        None => important_paths::find_root_manifest_for_wd(&cwd)?
+    };
+    // Where cargo-patch/ goes, relative paths given as flags are still relative to the current directory
+    let project = match args.manifest_path {
+        Some(_) => manifest.parent().expect("Manifest path didn't have parent").to_path_buf(),
+        None => cwd.clone()
+    };
+    let mut config = Config::default()?;
+    let unstable = if args.offline { vec![String::from("offline")] } else { Vec::new() };
+    let quiet = if args.quiet { Some(true) } else { None };
+    config.configure(args.verbose, quiet, &args.color, args.frozen, args.locked, &unstable)?;
+    let workspace = manifest::workspace(&manifest, &config)?;
 
-    if let Some(values) = matches.values_of("replace") {
-        for value in values {
-            let mut parts = value.splitn(2, '=');
-            match (parts.next(), parts.next()) {
-                (Some(name), Some(url)) => { replace.insert(name, url); },
-                _ => {
-                    eprintln!("Incorrect syntax for replace.");
-                    eprintln!("Use name=url");
-                    return Ok(());
-                }
-            }
+    // Flags take precedence over the settings in Patch.toml, which take
+    // precedence over the ones in the root manifest's metadata
+    let mut patch_files = Vec::new();
+    if let Some(path) = patchfile::find(workspace.root()) {
+        let file = PatchFile::load(&path)
+            .context(format!("couldn't read {}", path.display()))
+            .context(Code::InvalidPatchFile)?;
+        patch_files.push((path, file));
+    }
+    let root_manifest = workspace.root().join("Cargo.toml");
+    let from_manifest = PatchFile::from_manifest(&root_manifest)
+        .context(format!("couldn't read [package.metadata.patch] in {}", root_manifest.display()))
+        .context(Code::InvalidPatchFile)?;
+    if let Some(file) = from_manifest {
+        patch_files.push((root_manifest, file));
+    }
+    for (path, file) in &patch_files {
+        if let Err(err) = file.apply(path.parent().unwrap_or(Path::new("")), &mut args) {
+            return Err(errors::error(Code::InvalidPatchFile, format!("{}: {}", path.display(), err)));
         }
     }
+    // The config was made before the files could turn on verbose output
+    if args.verbose > 0 && config.shell().verbosity() == Verbosity::Normal {
+        config.shell().set_verbosity(Verbosity::Verbose);
+    }
 
-    let cwd = env::current_dir()?;
-    let manifest = important_paths::find_root_manifest_for_wd(&cwd)?;
-    let config = Config::default()?;
-    let workspace = Workspace::new(&manifest, &config)?;
-    let package = workspace.current()?;
+    let shadow = match args.mode() {
+        Mode::Shadow => Some(workspace.target_dir().into_path_unlocked().join("patch")),
+        Mode::Copy | Mode::PatchSection | Mode::PathsOverride => None
+    };
+    let basedir = match shadow {
+        Some(ref shadow) => shadow.join("cargo-patch"),
+        None => match args.output_dir {
+            Some(ref dir) => cwd.join(dir),
+            None => project.join("cargo-patch")
+        }
+    };
+    // Where a file of the workspace should be written to
+    let locate = |path: &Path| match shadow {
+        Some(ref shadow) => shadow::locate(workspace.root(), shadow, path),
+        None => path.to_path_buf()
+    };
 
-    let (packages, resolve) = ops::resolve_ws(&workspace)?;
+    let features = args.features();
 
-    let basedir = cwd.join("cargo-patch");
-    if !basedir.exists() {
-        fs::create_dir(&basedir)?;
-    } else if !basedir.is_dir() {
-        eprintln!("File \"cargo-patch\" exists but is not a folder.");
-        eprintln!("But I need this directory...");
+    // The replacements picked are used like the ones given with --replace
+    if args.interactive {
+        let graph = graph::resolve(&workspace, features.as_ref())?;
+        let packages = roots(&workspace, args.package.as_ref().map(String::as_str))?;
+        let roots: Vec<usize> = packages.iter().filter_map(|package| graph.find(package.package_id())).collect();
+        let chosen = interactive::choose(&graph, &roots, &state::State::load(&basedir)?, &basedir)?;
+        if chosen.is_empty() {
+            config.shell().status("Fresh", "nothing to replace")?;
+            return Ok(());
+        }
+        args.replace.extend(chosen);
+    }
+
+    // Forking is followed by patching, like with --replace <name>=path:<fork>
+    let fork = match args.command {
+        Some(Command::Fork { ref name }) => Some(name.clone()),
+        _ => None
+    };
+    if let Some(name) = fork {
+        let dir = fork::fork(&config, &workspace, features.as_ref(), &basedir, &name)?;
+        args.replace.push(cli::Replace {
+            name,
+            selector: selector::Selector::default(),
+            target: None,
+            url: format!("path:{}", dir.display())
+        });
+    } else if let Some(command) = args.command.as_ref().filter(|command| !command.patches()) {
+        match *command {
+            Command::AdoptVendor { ref dir } => {
+                let root = workspace.root();
+                adopt::adopt(&config, &basedir, &locate(&root.join("Cargo.toml")), &locate(&root.join(dir)))?;
+            },
+            Command::Clean { keep_modified, force } => unpatch::clean(&mut config.shell(), &basedir, keep_modified, force)?,
+            Command::Completions { .. } => completions::crate_names(&workspace.root().join("Cargo.lock"))?,
+            Command::Diff { ref name } => diff::print(&mut config.shell(), &basedir, name.as_ref().map(String::as_str))?,
+            Command::Update { ref name } => refresh::update(&mut config.shell(), &basedir, name.as_ref().map(String::as_str))?,
+            Command::Export { ref name, ref output } => {
+                diff::export(&mut config.shell(), &basedir, name.as_ref().map(String::as_str), &workspace.root().join(output))?;
+            },
+            Command::Import { ref input } => {
+                diff::import(&mut config.shell(), &basedir, &workspace.root().join(input), &project, args.mode())?;
+            },
+            Command::VerifyIntegrity { restore } => {
+                integrity::verify(&mut config.shell(), &basedir, restore)?;
+            },
+            Command::Verify { build, message_format } => {
+                let intact = integrity::verify(&mut config.shell(), &basedir, false)?;
+                let current = integrity::drift(&mut config.shell(), &basedir)?;
+                if !intact || !current {
+                    return Err(errors::error(Code::CopiesChanged, "Run cargo patch verify-integrity --restore, or cargo patch update"));
+                }
+                if build {
+                    build::build(&mut config.shell(), &locate(&manifest), &basedir, message_format == MessageFormat::Json)?;
+                }
+            },
+            Command::ExportScript { ref output } => {
+                let members: Vec<_> = workspace.members().map(|member| locate(member.manifest_path())).collect();
+                let script = script::export(&locate(workspace.root()), &basedir, &members)?;
+                match *output {
+                    Some(ref file) => {
+                        fs::write(file, script)?;
+                        config.shell().status("Wrote", file.display())?;
+                    },
+                    None => print!("{}", script)
+                }
+            },
+            Command::Impact {} => {
+                let patched = manifest::workspace(&locate(&manifest), &config)?;
+                impact::print(&graph::resolve(&patched, features.as_ref())?, &basedir)?;
+            },
+            Command::LockDiff {} => lockdiff::print(&workspace)?,
+            Command::RetryUpstream { ref test } => {
+                let members: Vec<_> = workspace.members().map(|member| locate(member.manifest_path())).collect();
+                let lockfile = locate(&workspace.root().join("Cargo.lock"));
+                retry::retry(&config, &basedir, &members, &lockfile, test)?;
+            },
+            Command::Send { ref name, ref push } => send::send(&mut config.shell(), &basedir, name, push.as_ref().map(String::as_str))?,
+            Command::Status { check } => {
+                if !status::print(&basedir)? && check {
+                    return Err(errors::error(Code::ReviewOverdue, "Review them, and give them a new expiry date"));
+                }
+            },
+            Command::Sync { ref name } => {
+                let manifests: Vec<_> = workspace.members().map(|member| locate(member.manifest_path())).collect();
+                sync::sync(&config, &basedir, &manifests, name)?;
+            },
+            Command::List {} | Command::Tree { .. } => {
+                let graph = graph::resolve(&workspace, features.as_ref())?;
+                let packages = roots(&workspace, args.package.as_ref().map(String::as_str))?;
+                let state = state::State::load(&basedir)?;
+                for package in packages {
+                    let root = match graph.find(package.package_id()) {
+                        Some(root) => root,
+                        None => return Err(errors::error(Code::MissingRootPackage, format!("{} isn't in the resolved graph", package)))
+                    };
+                    match *command {
+                        Command::Tree { full } => tree::print(&graph, root, &state, &basedir, full),
+                        _ => tree::chains(&graph, root, &state, &basedir)
+                    }
+                }
+            },
+            Command::Unpatch { remove_copies, force } => unpatch::unpatch(&mut config.shell(), &basedir, remove_copies, force)?,
+            Command::Fork { .. } | Command::Plan { .. } | Command::Apply { .. } => unreachable!()
+        }
         return Ok(());
     }
 
-    let mut cache = HashSet::with_capacity(64);
-    let mut stack = Vec::with_capacity(64);
+    // A plan is written instead of patching, or patching does exactly what a plan says
+    let planning = match args.command {
+        Some(Command::Plan { ref output }) => Some(output.clone()),
+        _ => None
+    };
+    let applying = match args.command {
+        Some(Command::Apply { ref plan }) => Some(Plan::load(plan)?),
+        _ => None
+    };
 
-    stack.push(StackEntry {
-        package: package,
-        dependencies: resolve.deps(package.package_id()),
-        updated: None
-    });
+    let resume = args.resume;
+    let previous = journal::Journal::load(&basedir)?;
+    match previous {
+        Some(_) if !resume => return Err(errors::error(Code::InterruptedRun, "Run with --resume to continue it")),
+        None if resume => return Err(errors::error(Code::NothingToResume, "")),
+        _ => ()
+    }
+    let mut replace = HashMap::with_capacity(16);
+    // Replacements only done for one target, like winapi[cfg(windows)]
+    let mut targets = HashMap::new();
+    // Replacements only done for some versions, like foo@1.2
+    let mut selectors = HashMap::new();
+    let mut annotations = HashMap::new();
+    // Where each replacement of this run was given, to catch conflicting ones
+    let mut given = HashMap::new();
+
+    if let Some(ref plan) = applying {
+        for (name, url) in &plan.replace {
+            replace.insert(name.clone(), url.clone());
+        }
+        for (name, cfg) in &plan.targets {
+            targets.insert(name.clone(), cfg.clone());
+        }
+        for (name, selector) in &plan.selectors {
+            selectors.insert(name.clone(), selector.clone());
+        }
+        for (name, annotation) in &plan.annotations {
+            annotations.insert(name.clone(), annotation.clone());
+        }
+    }
+    if let Some(ref journal) = previous {
+        for (name, url) in &journal.replace {
+            replace.insert(name.clone(), url.clone());
+        }
+        for (name, cfg) in &journal.targets {
+            targets.insert(name.clone(), cfg.clone());
+        }
+        for (name, selector) in &journal.selectors {
+            selectors.insert(name.clone(), selector.clone());
+        }
+        for (name, annotation) in &journal.annotations {
+            annotations.insert(name.clone(), annotation.clone());
+        }
+    }
+
+    // The manifest's metadata comes first, then Patch.toml, so that files and
+    // flags given explicitly take precedence.
+    // A resumed run already has the replacements it was started with, and
+    // an applied plan has all of them.
+    let mut entries = Vec::new();
+    if !resume && applying.is_none() {
+        for (path, file) in patch_files.iter().rev() {
+            for (name, spec) in &file.replace {
+                entries.push((path.clone(), name.clone(), spec.clone()));
+            }
+        }
+    }
+    let replace_toml: &[PathBuf] = if applying.is_none() { &args.replace_toml } else { &[] };
+    for file in replace_toml {
+        for (name, patch) in patches::read(file)? {
+            entries.push((file.clone(), name, patch.spec));
+        }
+    }
+    for (file, spec_name, spec) in entries {
+        let display = file.display();
+        let (name, selector) = match selector::parse(&spec_name) {
+            Ok(parsed) => parsed,
+            Err(err) => return Err(errors::error(Code::InvalidReplaceSyntax, format!("{}: {}", display, err)))
+        };
+        let url = match patches::location(&spec, file.parent().unwrap_or(Path::new(""))) {
+            Ok(url) => url,
+            Err(err) => return Err(errors::error(Code::InvalidReplaceSyntax, format!("{}: {} {}", display, name, err)))
+        };
+        let unsupported: Vec<&String> = spec.as_table()
+            .map(|table| table.keys().filter(|key| !patches::KEYS.contains(&key.as_str())).collect())
+            .unwrap_or_default();
+        if !unsupported.is_empty() {
+            return Err(errors::error(Code::InvalidReplaceSyntax, format!(
+                "{}: {} uses {:?}, but only {} are supported",
+                display, name, unsupported, patches::KEYS.join(", ")
+            )));
+        }
+        if let Some(reason) = patches::unmet_condition(&spec, args.profile()) {
+            config.shell().status("Ignoring", format!("{}: {}", name, reason))?;
+            continue;
+        }
+        let target = spec.get("target").and_then(|cfg| cfg.as_str()).map(String::from);
+        let spec_given = (url.clone(), target.clone(), selector.clone());
+        check_conflict(&mut config.shell(), &mut given, args.on_conflict(), &name, display.to_string(), spec_given)?;
+        let annotation = patches::annotation(&spec);
+        if !annotation.is_empty() {
+            annotations.insert(name.clone(), annotation);
+        }
+        match target {
+            Some(cfg) => targets.insert(name.clone(), cfg),
+            None => targets.remove(&name)
+        };
+        if selector.is_empty() {
+            selectors.remove(&name);
+        } else {
+            selectors.insert(name.clone(), selector);
+        }
+        replace.insert(name, url);
+    }
+
+    let annotation = state::Annotation {
+        owner: args.owner.clone(),
+        reason: args.reason.clone(),
+        upstream: args.upstream.clone(),
+        expires: args.expires.clone(),
+        max_age: args.max_age,
+        added: None
+    };
+    // Files given with --replace-file come first, so --replace can override them
+    let mut flags = Vec::new();
+    let replace_file: &[PathBuf] = if applying.is_none() { &args.replace_file } else { &[] };
+    for file in replace_file {
+        let contents = if file == Path::new("-") {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+            contents
+        } else {
+            fs::read_to_string(file)?
+        };
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.parse::<cli::Replace>() {
+                Ok(value) => flags.push((format!("{}:{}", file.display(), i + 1), value)),
+                Err(err) => return Err(errors::error(Code::InvalidReplaceSyntax, format!("{}:{}: {}", file.display(), i + 1, err)))
+            }
+        }
+    }
+    if applying.is_none() {
+        flags.extend(args.replace.iter().map(|value| (String::from("--replace"), value.clone())));
+    }
+    for (origin, value) in flags {
+        let spec_given = (value.url.clone(), value.target.clone(), value.selector.clone());
+        check_conflict(&mut config.shell(), &mut given, args.on_conflict(), &value.name, origin, spec_given)?;
+        replace.insert(value.name.clone(), value.url.clone());
+        if !annotation.is_empty() {
+            annotations.insert(value.name.clone(), annotation.clone());
+        }
+        match value.target {
+            Some(ref cfg) => targets.insert(value.name.clone(), cfg.clone()),
+            None => targets.remove(&value.name)
+        };
+        if value.selector.is_empty() {
+            selectors.remove(&value.name);
+        } else {
+            selectors.insert(value.name.clone(), value.selector.clone());
+        }
+    }
+
+    let today = dates::format(dates::today());
+    for (name, annotation) in annotations.iter_mut() {
+        if let Some(ref expires) = annotation.expires {
+            if dates::parse(expires).is_none() {
+                return Err(errors::error(Code::InvalidReplaceSyntax, format!(
+                    "The expiry date of {} should look like 2018-12-31, not {:?}",
+                    name, expires
+                )));
+            }
+        }
+        if annotation.added.is_none() {
+            annotation.added = Some(today.clone());
+        }
+    }
 
-    loop {
-        let mut to_add = None;
-        {
-            let entry = match stack.last_mut() {
-                Some(entry) => entry,
-                None => break
+    // Pull requests are looked up once, and their head branch is used from then on
+    for (name, url) in replace.iter_mut() {
+        if !url.starts_with(pulls::PREFIX) {
+            continue;
+        }
+        let pull = match pulls::parse(&url[pulls::PREFIX.len()..]) {
+            Ok(pull) => pull,
+            Err(err) => return Err(errors::error(Code::InvalidReplacementUrl, format!("Replacement for {}: {}", name, err)))
+        };
+        match pulls::head(&config, &pull)? {
+            Some(head) => {
+                config.shell().status("Resolved", format!("{} to {}", pull, head))?;
+                *url = head;
+            },
+            None => return Err(errors::error(Code::PullRequestNotFound, format!(
+                "Replacement for {}: {} doesn't exist, or its branch is gone",
+                name, pull
+            )))
+        }
+    }
+    let rewrites = urls::Rewrites::load();
+    for (name, url) in replace.iter_mut() {
+        if url.starts_with(tarball::VERSION) {
+            if Version::parse(&url[tarball::VERSION.len()..]).is_err() {
+                return Err(errors::error(Code::InvalidReplacementUrl, format!(
+                    "Replacement for {}: expected an exact version like 1.2.3 after {}",
+                    name, tarball::VERSION
+                )));
+            }
+            continue;
+        }
+        // Tarballs are checked by downloading them
+        if tarball::download(&basedir, name, url).is_some() {
+            continue;
+        }
+        match urls::validate(&rewrites.apply(url), &cwd) {
+            Ok(valid) => *url = valid,
+            Err(err) => return Err(errors::error(Code::InvalidReplacementUrl, format!("Replacement for {}: {}", name, err)))
+        }
+    }
+    // Local replacements are only read from, but still shouldn't be outside of the project
+    if !args.allow_outside_paths {
+        let project = Sandbox::new(&[workspace.root()]);
+        for (name, url) in &replace {
+            let path = match urls::local_path(url) {
+                Some(path) => Some(path),
+                None if url.starts_with("file://") => Some(Path::new(&url["file://".len()..])),
+                None => None
             };
+            if path.map(|path| !project.contains(path)).unwrap_or(false) {
+                return Err(errors::error(Code::OutsideSandbox, format!(
+                    "The replacement for {} ({}) is outside of {}\nPass --allow-outside-paths if that's intended",
+                    name, url, workspace.root().display()
+                )));
+            }
+        }
+    }
+    // Tarballs and published versions are unpacked next to the copies, and used like local checkouts
+    for (name, url) in replace.iter_mut() {
+        let (source, dir) = match tarball::download(&basedir, name, url) {
+            Some(download) => download,
+            None => continue
+        };
+        if !dir.exists() {
+            config.shell().status("Downloading", &source)?;
+        }
+        if !tarball::unpack(&config, &source, &dir)? {
+            return Err(errors::error(Code::DownloadFailed, format!(
+                "Replacement for {}: {} couldn't be downloaded and unpacked",
+                name, source
+            )));
+        }
+        *url = format!("path:{}", dir.display());
+    }
 
-            if let Some(id) = entry.dependencies.next() {
-                let package = packages.get(&id)?;
+    let packages = roots(&workspace, args.package.as_ref().map(String::as_str))?;
 
-                if let Some(url) = replace.get(&*package.name()) {
-                    entry.updated.get_or_insert_with(|| HashMap::with_capacity(4))
-                        .insert(package.name().to_string(), PackagePath::Git(url));
-                    continue;
-                } else if cache.contains(&entry.package.package_id()) {
-                    let name = entry.package.name().to_string();
+    // The shadow workspace is thrown away anyway
+    if !args.allow_dirty && shadow.is_none() {
+        let mut dirty = vcs::dirty_manifests(workspace.root())?;
+        if let Some(ref journal) = previous {
+            // The interrupted run is allowed to have touched these
+            dirty.retain(|file| !journal.manifests.contains_key(&*file.to_string_lossy()));
+        }
+        if !dirty.is_empty() {
+            let files: Vec<String> = dirty.iter().map(|file| format!("  {}", file.display())).collect();
+            return Err(errors::error(Code::DirtyManifests, format!(
+                "{}\nCommit or stash them first, or pass --allow-dirty",
+                files.join("\n")
+            )));
+        }
+    }
 
-                    let path = basedir.join(&name);
-                    entry.updated.get_or_insert_with(|| HashMap::with_capacity(4))
-                        .insert(name.clone(), PackagePath::Path(path));
-                } else {
-                    // Can't push while .last() is borrowed
-                    to_add = Some(package);
+    let graph = graph::resolve(&workspace, features.as_ref())?;
+    let mut roots = Vec::with_capacity(packages.len());
+    for package in packages {
+        match graph.find(package.package_id()) {
+            Some(i) => roots.push(i),
+            None => return Err(errors::error(Code::MissingRootPackage, format!("{} isn't in the resolved graph", package)))
+        }
+    }
+    let members: HashSet<String> = workspace.members()
+        .map(|member| member.package_id().to_string())
+        .collect();
+
+    // Names like tokio-* stand for every crate in the tree they match,
+    // except the members and the ones replaced by name
+    let patterns: Vec<String> = replace.keys()
+        .filter(|name| name.contains(|c| c == '*' || c == '?'))
+        .cloned()
+        .collect();
+    for pattern in patterns {
+        let url = replace.remove(&pattern).expect("Pattern wasn't replaced");
+        let target = targets.remove(&pattern);
+        let selector = selectors.remove(&pattern);
+        let annotation = annotations.remove(&pattern);
+        let mut names: Vec<&str> = graph.nodes.iter()
+            .filter(|node| !members.contains(&node.id) && glob::matches(&pattern, &node.name))
+            .filter(|node| selector.as_ref().map(|selector| selector.matches(node)).unwrap_or(true))
+            .map(|node| node.name.as_str())
+            .collect();
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            return Err(errors::error(Code::ReplacementNotFound, format!("Nothing in the dependency tree matches {:?}", pattern)));
+        }
+        for name in names {
+            if replace.contains_key(name) {
+                continue;
+            }
+            replace.insert(name.to_string(), url.clone());
+            if let Some(ref cfg) = target {
+                targets.insert(name.to_string(), cfg.clone());
+            }
+            if let Some(ref selector) = selector {
+                selectors.insert(name.to_string(), selector.clone());
+            }
+            if let Some(ref annotation) = annotation {
+                annotations.insert(name.to_string(), annotation.clone());
+            }
+        }
+    }
+
+    // The resolved graph already has the root's [patch] entries applied,
+    // so copies are made from the effective sources
+    let patches = patches::read(&workspace.root().join("Cargo.toml"))?;
+    for (name, url) in &replace {
+        if let Some(patch) = patches.get(name) {
+            if args.merge_patch {
+                config.shell().status("Merging", format!(
+                    "{} into [patch.{}], which pointed to {}",
+                    url, patch.registry, patches::describe(&patch.spec)
+                ))?;
+            } else {
+                config.shell().warn(format!(
+                    "{} is already overridden by [patch.{}] ({}). Every dependent will be rewritten to use {} instead, \
+                     leaving that entry unused. Pass --merge-patch to point it to the replacement too",
+                    name, patch.registry, patches::describe(&patch.spec), url
+                ))?;
+            }
+        }
+    }
+
+    // Optional dependencies the features leave out aren't in the graph, but
+    // the crates declaring them are
+    let mut disabled = HashSet::new();
+    for node in &graph.nodes {
+        for dependency in node.dependencies.iter().filter(|dependency| dependency.optional && replace.contains_key(&dependency.name)) {
+            if !graph.deps(node).any(|dep| dep.name == dependency.name) {
+                skip::print(&mut config.shell(), node, &skip::Reason::FeatureDisabled(dependency.name.clone()))?;
+                disabled.insert(dependency.name.clone());
+            }
+        }
+    }
+
+    // Whether a crate is one of the ones its replacement is for
+    let selected = |node: &Node| selectors.get(&node.name).map(|selector| selector.matches(node)).unwrap_or(true);
+    let missing: Vec<&String> = replace.keys()
+        .filter(|name| !disabled.contains(*name) && !graph.nodes.iter().any(|node| node.name == **name && selected(node)))
+        .collect();
+    if !missing.is_empty() {
+        let lines: Vec<String> = missing.iter()
+            .map(|name| match selectors.get(*name) {
+                Some(selector) => format!("Nothing in the dependency tree is {}{}", name, selector),
+                None => format!("Nothing in the dependency tree is called {:?}", name)
+            })
+            .collect();
+        return Err(errors::error(Code::ReplacementNotFound, lines.join("\n")));
+    }
+
+    let mut replacement_versions = HashMap::new();
+    for (name, url) in &replace {
+        match versions::replacement(&config, name, url) {
+            Ok(Some(replacement)) => {
+                replacement.print(&mut config.shell(), name, url)?;
+                replacement_versions.insert(name.clone(), replacement.version);
+            },
+            Ok(None) => config.shell().warn(format!("{} not found in {}", name, url))?,
+            Err(err) => config.shell().warn(format!("failed to fetch {}: {}", url, err))?
+        }
+    }
+
+    // When there are several versions of a replaced crate, the ones the
+    // replacement isn't compatible with are left alone unless asked otherwise
+    let mut incompatible = HashSet::new();
+    if !args.dedupe_versions {
+        for (name, version) in &replacement_versions {
+            for i in versions::incompatible(&graph, name, version) {
+                if selected(&graph.nodes[i]) {
+                    incompatible.insert(graph.nodes[i].id.clone());
                 }
             }
         }
+    }
 
-        let mut name = None;
+    let mut violations = Vec::new();
+    for (name, version) in &replacement_versions {
+        violations.extend(versions::violations(&graph, name, version));
+    }
+    violations.retain(|violation| match graph.nodes.iter().find(|node| node.id == violation.dependent) {
+        Some(dependent) => !graph.deps(dependent)
+            .any(|dep| dep.name == violation.name && (incompatible.contains(&dep.id) || !selected(dep))),
+        None => true
+    });
+    let relax_version = args.relax_version();
+    if !violations.is_empty() {
+        config.shell().warn(format!(
+            "these version requirements aren't satisfied by the replacements:\n{}",
+            versions::table(&violations)
+        ))?;
+        if relax_version.is_none() {
+            config.shell().warn("cargo will reject them. Pass --relax-version widen or --relax-version drop to change them")?;
+        }
+    }
 
-        if let Some(package) = to_add {
-            let id = package.package_id();
-            if stack.iter().all(|entry| entry.package.package_id() != id) {
-                stack.push(StackEntry {
-                    package: package,
-                    dependencies: resolve.deps(&id).into_iter(),
-                    updated: None
-                });
-            } else {
-                eprintln!("Stuck in dependency loop!");
-                eprintln!("Package wants {}, but this appears previously in the stack!", package);
+    // The requirements to write instead of the violated ones, or None to drop them
+    let mut loosened = HashMap::new();
+    if let Some(relax) = relax_version {
+        for violation in &violations {
+            loosened.insert(
+                (violation.dependent.clone(), violation.name.clone()),
+                match relax {
+                    Relax::Widen => Some(versions::loosen(&violation.version)),
+                    Relax::Drop => None
+                }
+            );
+        }
+    }
+
+    // Replaced packages, except the ones already pointing to their replacement
+    let is_target = |node: &Node| match replace.get(&node.name) {
+        Some(url) => selected(node) && !incompatible.contains(&node.id) && !match urls::local_path(url) {
+            Some(path) => node.source.kind == SourceKind::Path && node.root().starts_with(path),
+            None => node.source.kind == SourceKind::Git && urls::same_source(&rewrites, &node.source.url, url)
+        },
+        None => false
+    };
+    for node in &graph.nodes {
+        if let Some(url) = replace.get(&node.name) {
+            if !selected(node) {
+                let spec = format!("{}{}", node.name, selectors[&node.name]);
+                skip::print(&mut config.shell(), node, &skip::Reason::NotSelected(spec))?;
+            } else if incompatible.contains(&node.id) {
+                skip::print(&mut config.shell(), node, &skip::Reason::IncompatibleVersion(replacement_versions[&node.name].clone()))?;
+            } else if !is_target(node) {
+                skip::print(&mut config.shell(), node, &skip::Reason::AlreadyReplaced(url.clone()))?;
+            }
+        }
+    }
+    if !incompatible.is_empty() {
+        config.shell().warn("pass --dedupe-versions to redirect the incompatible versions to the replacements too")?;
+    }
+    // The dependency tables left alone in members aren't followed, and neither
+    // are dependencies on a replacement restricted to a cfg, unless they're
+    // declared in a table of that cfg, since only those are redirected
+    let kinds = args.kinds();
+    let follows = |node: &Node, dep: &Node| {
+        let declared = |dependency: &&graph::Dependency| dependency.name == dep.name
+            && (!members.contains(&node.id) || kinds.contains(&dependency.kind.as_str()));
+        match targets.get(&dep.name) {
+            Some(cfg) if is_target(dep) => node.dependencies.iter()
+                .filter(declared)
+                .any(|dependency| dependency.target.as_ref().map(|target| manifest::same_cfg(target, cfg)).unwrap_or(false)),
+            _ => !members.contains(&node.id) || node.dependencies.iter().any(|dependency| declared(&dependency))
+        }
+    };
+    // Excluded crates are left alone, and so are their dependents unless they lead to a replacement another way
+    let is_excluded = |node: &Node| !members.contains(&node.id)
+        && args.exclude.iter().any(|pattern| glob::matches(pattern, &node.name));
+    for i in graph.dependents_except(&roots, &is_target, |_| false, &follows) {
+        let node = &graph.nodes[i];
+        if is_excluded(node) {
+            skip::print(&mut config.shell(), node, &skip::Reason::Excluded)?;
+        }
+    }
+    let dirty = graph.dependents_except(&roots, &is_target, &is_excluded, &follows);
+    let dirty_set: HashSet<usize> = dirty.iter().cloned().collect();
+    for i in graph.dependents(&roots, &is_target) {
+        let node = &graph.nodes[i];
+        if dirty_set.contains(&i) || is_excluded(node) {
+            continue;
+        }
+        let declared = |dep: &Node| node.dependencies.iter()
+            .any(|dependency| dependency.name == dep.name && (!members.contains(&node.id) || kinds.contains(&dependency.kind.as_str())));
+        for dep in graph.deps(node).filter(|dep| is_target(dep) && declared(dep) && !follows(node, dep)) {
+            if let Some(cfg) = targets.get(&dep.name) {
+                skip::print(&mut config.shell(), node, &skip::Reason::WrongTarget(dep.name.clone(), cfg.clone()))?;
+            }
+        }
+    }
+
+    for &i in &dirty {
+        for dep in graph.deps(&graph.nodes[i]) {
+            if is_target(dep) && members.contains(&dep.id) {
+                config.shell().status("Replacing", format!("workspace member {} with {}", dep.name, replace[&dep.name]))?;
+            }
+        }
+    }
+
+    // Workspace members are rewritten where they are, and don't need to be copied
+    let in_place = |i: usize| roots.contains(&i) || members.contains(&graph.nodes[i].id);
+
+    // Find out how to rewrite the dependencies of a package
+    let replaces_for = |node: &Node| {
+        let mut replaces = HashMap::with_capacity(4);
+        for &dep in &node.deps {
+            let package = &graph.nodes[dep];
+            if is_target(package) {
+                replaces.insert(package.name.clone(), PackagePath::replacement(&replace[&package.name]));
+            } else if dirty_set.contains(&dep) && !in_place(dep) {
+                replaces.insert(package.name.clone(), PackagePath::Path(basedir.join(package.dir_name())));
+            }
+        }
+        replaces
+    };
+
+    // Everything written has to stay inside the workspace and the copies, or the shadow workspace
+    let sandbox = match shadow {
+        Some(ref shadow) => Sandbox::new(&[shadow]),
+        None => Sandbox::new(&[workspace.root(), &basedir])
+    };
+    let describe = |sandbox: &Sandbox| sandbox.roots().iter()
+        .map(|root| root.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" and ");
+
+    let update_deny = |state: &mut state::State| -> CargoResult<()> {
+        if !args.update_deny {
+            return Ok(());
+        }
+        // Local checkouts aren't sources cargo-deny knows about
+        let urls: Vec<&str> = replace.values()
+            .filter(|url| urls::local_path(url).is_none() && urls::renamed(url).is_none())
+            .map(|url| urls::split_reference(url).0)
+            .collect();
+        // Both the originals and the copies/replacements will show up in the graph
+        let duplicates: Vec<(String, Version)> = graph.nodes.iter()
+            .enumerate()
+            .filter(|&(i, node)| is_target(node) || (dirty_set.contains(&i) && !in_place(i)))
+            .map(|(_, node)| (node.name.clone(), node.version.clone()))
+            .collect();
+        let path = locate(&workspace.root().join("deny.toml"));
+        if !sandbox.contains(&path) {
+            return Err(errors::error(Code::OutsideSandbox, format!(
+                "Not updating {}, it leads outside of {}",
+                path.display(), describe(&sandbox)
+            )));
+        }
+        // A deny.toml that didn't exist is removed again by unpatch
+        if path.exists() {
+            state.remember(&path, &fs::read_to_string(&path)?);
+        } else {
+            state.remember_missing(&path);
+        }
+        let original = state.originals.get(&*path.to_string_lossy()).cloned().unwrap_or_default();
+        deny::update(&path, &urls, &original, &duplicates)?;
+        state.rewrote(&path)?;
+        config.shell().status("Updated", path.display())?;
+        Ok(())
+    };
+    // With --exclude-copies, the copies can't be taken for members of the workspace
+    let copies_dir = || -> Option<(PathBuf, String)> {
+        let root_manifest = locate(&workspace.root().join("Cargo.toml"));
+        let relative = basedir.strip_prefix(root_manifest.parent()?).ok()?.to_string_lossy().into_owned();
+        Some((root_manifest, relative))
+    };
+    let exclude_copies = |state: &mut state::State| -> CargoResult<()> {
+        if !args.exclude_copies {
+            return Ok(());
+        }
+        // Copies outside of the workspace aren't in it anyway
+        let (path, dir) = match copies_dir() {
+            Some(copies) => copies,
+            None => return Ok(())
+        };
+        let contents = fs::read_to_string(&path)?;
+        match manifest::exclude(&contents, &dir)? {
+            Some(ref excluded) if *excluded != contents => {
+                state.remember(&path, &contents);
+                fs::write(&path, excluded)?;
+                state.rewrote(&path)?;
+                config.shell().status("Excluded", format!("{} from the workspace in {}", dir, path.display()))?;
+            },
+            // Without a [workspace], nothing below the root is taken for a member
+            _ => ()
+        }
+        Ok(())
+    };
+    // The rewritten manifests leave Cargo.lock with the old sources and checksums
+    let update_lock = |state: &mut state::State| -> CargoResult<()> {
+        if !args.update_lock {
+            return Ok(());
+        }
+        let path = locate(&workspace.root().join("Cargo.lock"));
+        if !sandbox.contains(&path) {
+            return Err(errors::error(Code::OutsideSandbox, format!(
+                "Not updating {}, it leads outside of {}",
+                path.display(), describe(&sandbox)
+            )));
+        }
+        if path.exists() {
+            state.remember(&path, &fs::read_to_string(&path)?);
+        }
+        let replaced: Vec<&str> = replace.keys().map(String::as_str).collect();
+        if lockfile::update(&config, &locate(&workspace.root().join("Cargo.toml")), &replaced)? {
+            config.shell().status("Updated", path.display())?;
+        } else {
+            config.shell().warn(format!(
+                "couldn't resolve the patched workspace, so only the stale checksums were removed from {}",
+                path.display()
+            ))?;
+        }
+        Ok(())
+    };
+
+    if dirty.is_empty() && previous.is_none() {
+        config.shell().status("Fresh", "nothing depends on the replaced crates")?;
+        return Ok(());
+    }
+    // Every rewritten dependency ends up in Cargo.lock
+    if !config.lock_update_allowed() {
+        return Err(errors::error(Code::LockfileChange, format!(
+            "{} manifests would be rewritten, but Cargo.lock may not change",
+            dirty.len()
+        )));
+    }
+
+    // With --mode paths-override, the local replacements are added to the
+    // paths overrides of the workspace's cargo configuration, and nothing is
+    // copied or rewritten
+    if args.mode() == Mode::PathsOverride {
+        if planning.is_some() || applying.is_some() {
+            config.shell().warn("--mode paths-override doesn't copy anything, so there's no plan to make or apply")?;
+            return Ok(());
+        }
+        let remote: Vec<(&String, &String)> = replace.iter().filter(|(_, url)| urls::local_path(url).is_none()).collect();
+        if !remote.is_empty() {
+            let lines: Vec<String> = remote.iter()
+                .map(|(name, url)| format!("Paths overrides only work with local checkouts, but {} is replaced with {}", name, url))
+                .collect();
+            return Err(errors::error(Code::InvalidReplacementUrl, lines.join("\n")));
+        }
+        let mut dirs: Vec<&Path> = replace.values().filter_map(|url| urls::local_path(url)).collect();
+        dirs.sort();
+        dirs.dedup();
+        let path = overrides::config_path(workspace.root());
+        let print_dirs = || {
+            for dir in &dirs {
+                events::say(format!("  add {} to paths in {}", dir.display(), path.display()));
+            }
+        };
+        if args.dry_run {
+            events::say("This would:");
+            print_dirs();
+            return Ok(());
+        }
+        if !args.yes && atty::is(atty::Stream::Stdin) {
+            events::say("This will:");
+            print_dirs();
+            if !confirm("Continue?")? {
+                events::say("Nothing was changed");
                 return Ok(());
             }
-        } else if let Some(entry) = stack.pop() {
-            let package = entry.package;
-            if cache.contains(&entry.package.package_id()) {
-                name = Some(package.name());
-            } else if let Some(ref replaces) = entry.updated {
-                let _dest;
-                let manifest = if !stack.is_empty() {
-                    let path = package.manifest_path().parent().expect("Manifest path didn't have parent");
-                    let dest = basedir.join(&*package.name());
-                    if dest.exists() {
-                        println!("Skipping {}", package.name());
+        }
+        if !violations.is_empty() {
+            config.shell().warn("paths overrides can't change the version requirements above, so cargo will reject them")?;
+        }
+        let existed = path.exists();
+        let contents = if existed { fs::read_to_string(&path)? } else { String::new() };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, overrides::add_paths(&contents, &dirs)?)?;
+        config.shell().status("Rewrote", path.display())?;
+        if !basedir.exists() {
+            fs::create_dir_all(&basedir)?;
+        }
+        let mut state = state::State::load(&basedir)?;
+        if existed {
+            state.remember(&path, &contents);
+        } else {
+            state.remember_missing(&path);
+        }
+        state.rewrote(&path)?;
+        state.replace.extend(replace.clone());
+        for (name, annotation) in &annotations {
+            state.annotate(name, annotation.clone());
+        }
+        state.save(&basedir)?;
+        return Ok(());
+    }
+
+    // With --mode patch-section, the root manifest gets a [patch] entry for
+    // every replaced crate, in the table of the source it comes from, and
+    // nothing is copied
+    if args.mode() == Mode::PatchSection {
+        if planning.is_some() || applying.is_some() {
+            config.shell().warn("--mode patch-section doesn't copy anything, so there's no plan to make or apply")?;
+            return Ok(());
+        }
+        let mut entries: Vec<(String, String, PackagePath)> = Vec::new();
+        for node in graph.nodes.iter().filter(|node| is_target(node)) {
+            let source = if node.source.default_registry { String::from("crates-io") } else { node.source.url.clone() };
+            if !entries.iter().any(|(other, name, _)| *other == source && *name == node.name) {
+                entries.push((source, node.name.clone(), PackagePath::replacement(&replace[&node.name])));
+            }
+        }
+        let root_manifest = workspace.root().join("Cargo.toml");
+        let print_entries = || {
+            for (source, name, _) in &entries {
+                events::say(format!("  add {} to [patch.{:?}] in {}", name, source, root_manifest.display()));
+            }
+        };
+        if args.dry_run {
+            events::say("This would:");
+            print_entries();
+            return Ok(());
+        }
+        if !args.yes && atty::is(atty::Stream::Stdin) {
+            events::say("This will:");
+            print_entries();
+            if !confirm("Continue?")? {
+                events::say("Nothing was changed");
+                return Ok(());
+            }
+        }
+        if !violations.is_empty() {
+            config.shell().warn("cargo only uses [patch] entries that satisfy the version requirements, so the ones above stay unpatched")?;
+        }
+        let contents = fs::read_to_string(&root_manifest)?;
+        fs::write(&root_manifest, manifest::add_patches(&contents, &entries)?)?;
+        config.shell().status("Rewrote", root_manifest.display())?;
+        if !basedir.exists() {
+            fs::create_dir_all(&basedir)?;
+        }
+        let mut state = state::State::load(&basedir)?;
+        state.remember(&root_manifest, &contents);
+        state.rewrote(&root_manifest)?;
+        state.replace.extend(replace.clone());
+        for (name, annotation) in &annotations {
+            state.annotate(name, annotation.clone());
+        }
+        update_lock(&mut state)?;
+        update_deny(&mut state)?;
+        state.save(&basedir)?;
+        return Ok(());
+    }
+
+    let mut destinations: Vec<PathBuf> = dirty.iter()
+        .map(|&i| if in_place(i) {
+            locate(&graph.nodes[i].manifest_path)
+        } else {
+            basedir.join(graph.nodes[i].dir_name()).join("Cargo.toml")
+        })
+        .collect();
+    if args.update_deny {
+        destinations.push(locate(&workspace.root().join("deny.toml")));
+    }
+    let outside: Vec<&PathBuf> = destinations.iter().filter(|path| !sandbox.contains(path)).collect();
+    if !outside.is_empty() {
+        let paths: Vec<String> = outside.iter().map(|path| format!("  {}", path.display())).collect();
+        return Err(errors::error(Code::OutsideSandbox, format!(
+            "These would be written outside of {}:\n{}",
+            describe(&sandbox), paths.join("\n")
+        )));
+    }
+
+    let print_plan = || {
+        for &i in &dirty {
+            let node = &graph.nodes[i];
+            if in_place(i) {
+                events::say(format!("  rewrite {}", locate(&node.manifest_path).display()));
+            } else {
+                events::say(format!("  copy {} {} to {} and rewrite it", node.name, node.version, basedir.join(node.dir_name()).display()));
+            }
+            let mut replaces: Vec<_> = replaces_for(node).into_iter().collect();
+            replaces.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, path) in replaces {
+                match path {
+                    PackagePath::Git(url) => events::say(format!("    {} -> {}", name, url)),
+                    PackagePath::Path(path) => events::say(format!("    {} -> {}", name, path.display())),
+                    PackagePath::Registry(version) => events::say(format!("    {} -> {}", name, version)),
+                    PackagePath::Renamed(package, req) => events::say(format!("    {} -> {} {}", name, package, req))
+                }
+            }
+        }
+        if args.update_deny {
+            events::say(format!("  update {}", locate(&workspace.root().join("deny.toml")).display()));
+        }
+        if args.exclude_copies {
+            if let Some((path, dir)) = copies_dir() {
+                events::say(format!("  add {} to workspace.exclude in {}", dir, path.display()));
+            }
+        }
+        if args.update_lock {
+            events::say(format!("  update {}", locate(&workspace.root().join("Cargo.lock")).display()));
+        }
+    };
+    let relative = |path: &Path| path.strip_prefix(workspace.root()).unwrap_or(path).display().to_string();
+    let steps: Vec<plan::Step> = dirty.iter().zip(&destinations)
+        .map(|(&i, manifest)| {
+            let node = &graph.nodes[i];
+            let dependencies = replaces_for(node).into_iter()
+                .map(|(name, path)| (name, match path {
+                    PackagePath::Git(url) => url.to_string(),
+                    PackagePath::Path(path) => relative(&path),
+                    PackagePath::Registry(version) => version.to_string(),
+                    PackagePath::Renamed(package, req) => format!("{} {}", package, req)
+                }))
+                .collect();
+            plan::Step {
+                package: node.id.clone(),
+                copy: if in_place(i) { None } else { Some(relative(&basedir.join(node.dir_name()))) },
+                manifest: relative(manifest),
+                dependencies
+            }
+        })
+        .collect();
+    if let Some(ref output) = planning {
+        let plan = Plan {
+            replace: replace.iter().map(|(name, url)| (name.clone(), url.clone())).collect(),
+            targets: targets.iter().map(|(name, cfg)| (name.clone(), cfg.clone())).collect(),
+            selectors: selectors.iter().map(|(name, selector)| (name.clone(), selector.clone())).collect(),
+            annotations: annotations.iter().map(|(name, annotation)| (name.clone(), annotation.clone())).collect(),
+            steps
+        };
+        plan.save(output)?;
+        config.shell().status("Wrote", format!("the plan to {}", output.display()))?;
+        events::say(format!("Carry it out with: cargo patch apply {}", output.display()));
+        return Ok(());
+    }
+    if let Some(ref plan) = applying {
+        let differences = plan.differences(&steps);
+        if !differences.is_empty() {
+            let lines: Vec<String> = differences.iter().map(|difference| format!("  {}", difference)).collect();
+            return Err(errors::error(Code::PlanMismatch, format!(
+                "{}\nMake a new plan with cargo patch plan",
+                lines.join("\n")
+            )));
+        }
+    }
+
+    if args.dry_run {
+        if events::enabled() {
+            events::emit(&Event::Plan { steps: &steps });
+        } else {
+            events::say("This would:");
+            print_plan();
+        }
+        return Ok(());
+    }
+
+    // Resumed runs were already confirmed, and plans reviewed
+    if previous.is_none() && applying.is_none() && !args.yes && atty::is(atty::Stream::Stdin) {
+        events::say("This will:");
+        print_plan();
+        if !confirm("Continue?")? {
+            events::say("Nothing was changed");
+            return Ok(());
+        }
+    }
+
+    if let Some(ref shadow) = shadow {
+        let members: Vec<_> = workspace.members().map(|member| member.root().to_path_buf()).collect();
+        shadow::create(&mut config.shell(), workspace.root(), &members, shadow)?;
+    }
+
+    let print_shadow = || -> CargoResult<()> {
+        if let Some(ref shadow) = shadow {
+            config.shell().status("Created", format!(
+                "patched workspace in {}, build it with `cargo build --manifest-path {}`",
+                shadow.display(), locate(&manifest).display()
+            ))?;
+        }
+        Ok(())
+    };
+
+    if !basedir.exists() {
+        fs::create_dir_all(&basedir)?;
+    } else if !basedir.is_dir() {
+        return Err(errors::error(Code::BaseDirNotFolder, format!("File {} exists but is not a folder.", basedir.display())));
+    }
+
+    // Members inheriting a replaced dependency from the workspace get their own spec for it
+    let inherited = manifest::Inherited::read(&locate(&workspace.root().join("Cargo.toml")))?;
+
+    // With --merge-patch, the root's [patch] and [replace] entries are pointed to the replacements too
+    let overrides: HashMap<String, PackagePath> = replace.iter()
+        .map(|(name, url)| (name.clone(), PackagePath::replacement(url)))
+        .collect();
+    let root_manifest = workspace.root().join("Cargo.toml");
+    let rewrite = |package: &Node, contents: &str, kinds: &[&str]| -> CargoResult<String> {
+        let rewritten = manifest::rewrite(contents, &package.id, kinds, &replaces_for(package), &targets, &loosened, &inherited)?;
+        if args.merge_patch && package.manifest_path == root_manifest {
+            return manifest::merge_overrides(&rewritten, &overrides);
+        }
+        Ok(rewritten)
+    };
+
+    // If only the root package depends on the replaced crates, there's nothing to copy
+    if dirty.len() == 1 && roots.contains(&dirty[0]) && previous.is_none() {
+        let root = &graph.nodes[dirty[0]];
+        let path = locate(&root.manifest_path);
+        let contents = fs::read_to_string(&path)?;
+        // Nothing was written yet, so a manifest that can't be rewritten leaves nothing to roll back
+        let rewritten = rewrite(root, &contents, &kinds)?;
+        fs::write(&path, rewritten)?;
+        config.shell().status("Rewrote", path.display())?;
+        events::emit(&Event::ManifestRewritten {
+            package_id: &root.id,
+            manifest: &path
+        });
+        let mut state = state::State::load(&basedir)?;
+        state.remember(&path, &contents);
+        state.rewrote(&path)?;
+        state.replace.extend(replace.clone());
+        for (name, annotation) in &annotations {
+            state.annotate(name, annotation.clone());
+        }
+        update_lock(&mut state)?;
+        update_deny(&mut state)?;
+        state.save(&basedir)?;
+        print_shadow()?;
+        if let Some(relax) = relax_version {
+            versions::print_loosened(&mut config.shell(), &violations, relax == Relax::Drop)?;
+        }
+        return Ok(());
+    }
+
+    let mut journal = match previous {
+        Some(journal) => journal,
+        None => {
+            let mut journal = journal::Journal::new(&basedir, replace.iter()
+                .map(|(name, url)| (name.to_string(), url.to_string()))
+                .collect());
+            journal.targets = targets.iter()
+                .map(|(name, cfg)| (name.to_string(), cfg.to_string()))
+                .collect();
+            journal.selectors = selectors.iter()
+                .map(|(name, selector)| (name.to_string(), selector.clone()))
+                .collect();
+            journal.annotations = annotations.iter()
+                .map(|(name, annotation)| (name.to_string(), annotation.clone()))
+                .collect();
+            journal
+        }
+    };
+    journal.save()?;
+
+    let mut state = state::State::load(&basedir)?;
+    // The globs are kept, so later runs and commands leave out the same files
+    for pattern in &args.copy_ignore {
+        if !state.ignore.contains(pattern) {
+            state.ignore.push(pattern.clone());
+        }
+    }
+
+    interrupt::install()?;
+    let mut rollback = interrupt::Rollback::default();
+
+    // Every step from the first write on goes through here, so that an error
+    // or Ctrl-C rolls back everything this run did
+    let result = {
+        let mut patch = || -> CargoResult<()> {
+            // Copy everything first, and collect the manifests to rewrite
+            let mut pending = Vec::with_capacity(dirty.len());
+            for &i in &dirty {
+                interrupt::check()?;
+
+                let package = &graph.nodes[i];
+                let in_place = in_place(i);
+                let replaces = replaces_for(package);
+                for (name, value) in &replaces {
+                    if let PackagePath::Git(url) = value {
+                        let is_root = package.manifest_path == workspace.root().join("Cargo.toml");
+                        patches::warn_overrides(&mut config.shell(), &package.manifest_path, is_root, name, url)?;
+                    }
+                }
+                let mut copied = false;
+                let manifest = if !in_place {
+                    let path = match vendor::vendored_path(&config, package)? {
+                        Some(path) => path,
+                        None => package.root().to_path_buf()
+                    };
+                    let dest = basedir.join(package.dir_name());
+                    if resume && dest.exists() && journal.copies.contains_key(&*dest.to_string_lossy())
+                            && !journal.is_copied(&dest)? {
+                        config.shell().status("Removing", format!("incomplete copy of {} {}", package.name, package.version))?;
+                        fs::remove_dir_all(&dest)?;
+                    }
+                    // With --force, existing copies are checked against their sources
+                    let recorded = if args.force && dest.exists() {
+                        state.copies.get(&package.dir_name())
+                    } else {
+                        None
+                    };
+                    if let Some(recorded) = recorded {
+                        match refresh::check(recorded, &path, &dest, &state.ignored(&package.dir_name()))? {
+                            Staleness::Stale => {
+                                config.shell().status("Updating", format!("the copy of {} {}", package.name, package.version))?;
+                                refresh::refresh(&path, &dest, &state.ignored(&package.dir_name()))?;
+                            },
+                            Staleness::Edited => config.shell().warn(format!(
+                                "not updating the copy of {} {}, it has local edits. Use `cargo patch sync` to merge them",
+                                package.name, package.version
+                            ))?,
+                            Staleness::Fresh => skip::print(&mut config.shell(), package, &skip::Reason::AlreadyCopied(dest.clone()))?
+                        }
+                    } else if dest.exists() {
+                        skip::print(&mut config.shell(), package, &skip::Reason::AlreadyCopied(dest.clone()))?;
                     } else {
-                        println!("Copying {}...", package.name());
-                        copy(&path, &dest)?;
+                        copied = true;
+                        match patches.get(&package.name) {
+                            Some(patch) => config.shell().status("Copying", format!(
+                                "{} {} from its [patch.{}] override", package.name, package.version, patch.registry
+                            ))?,
+                            None => config.shell().status("Copying", format!("{} {}", package.name, package.version))?
+                        }
+                        // With --packaged-only, only what `cargo package` would ship is copied
+                        let files = if args.packaged_only {
+                            let files: Vec<PathBuf> = packaged::files(&config, &path)?.into_iter()
+                                .filter(|file| !ignore::is_ignored(file, &state.ignore))
+                                .collect();
+                            Some(files)
+                        } else {
+                            None
+                        };
+                        journal.copies.insert(dest.to_string_lossy().into_owned(), String::new());
+                        journal.save()?;
+                        rollback.copied(dest.clone());
+                        let result = match files {
+                            Some(ref files) => packaged::copy(&path, &dest, files),
+                            None => copy::package(&path, &dest, &state.ignore)
+                        };
+                        result?;
+                        journal.record_copy(&dest)?;
+                        match files {
+                            Some(ref files) => {
+                                let left_out = packaged::left_out(&path, files, &state.ignore)?;
+                                state.left_out.insert(package.dir_name(), left_out);
+                            },
+                            None => {
+                                state.left_out.remove(&package.dir_name());
+                            }
+                        }
+                        if args.original_manifest {
+                            manifest::restore_original(&dest)?;
+                        }
+                        events::emit(&Event::CrateCopied {
+                            package_id: &package.id,
+                            source: &path,
+                            copy: &dest
+                        });
                     }
-                    name = Some(package.name());
-                    _dest = dest.join("Cargo.toml");
-                    &_dest
+                    dest.join("Cargo.toml")
                 } else {
-                    &manifest
+                    locate(&package.manifest_path)
                 };
-                let contents = fs::read_to_string(manifest)?;
-                let mut parsed: toml::Value = toml::from_str(&contents)?;
-                for table in &["dependencies", "dev-dependencies", "build-dependencies"] {
-                    if let Some(deps) = parsed.get_mut(table) {
-                        for (key, value) in replaces {
-                            if let Some(dep) = deps.get_mut(key) {
-                                fn change_path(map: &mut BTreeMap<String, toml::Value>, value: &PackagePath) {
-                                    for key in &["version", "path", "git"] {
-                                        map.remove(*key);
-                                    }
-                                    match value {
-                                        PackagePath::Path(path) => {
-                                            map.insert(String::from("path"), toml::Value::String(
-                                                path.to_string_lossy().into_owned()
-                                            ));
-                                        }
-                                        PackagePath::Git(url) => {
-                                            map.insert(String::from("git"), toml::Value::String(url.to_string()));
-                                        }
-                                    }
-                                }
-                                match dep {
-                                    toml::Value::Table(inner) => change_path(inner, value),
-                                    toml::Value::String(_) => {
-                                        let mut map = BTreeMap::new();
-                                        change_path(&mut map, value);
-                                        *dep = toml::Value::Table(map);
-                                    },
-                                    _ => {
-                                        eprintln!("Invalid value in Cargo.toml");
-                                        eprintln!("Dependency {:?} is not a string nor a table", key);
-                                        return Ok(());
-                                    }
-                                }
-                            }
+                if resume && journal.is_rewritten(&manifest)? {
+                    skip::print(&mut config.shell(), package, &skip::Reason::AlreadyRewritten(manifest))?;
+                    continue;
+                }
+                pending.push((i, manifest, copied));
+            }
+
+            // Every manifest is independent, so they're parsed and rewritten in parallel.
+            // With --low-memory, only one manifest is held in memory at a time instead.
+            let batch = if args.low_memory { 1 } else { pending.len().max(1) };
+            for pending in pending.chunks(batch) {
+                let rewritten = pending.par_iter()
+                    .map(|&(i, ref manifest, _)| -> CargoResult<_> {
+                        let package = &graph.nodes[i];
+                        let contents = fs::read_to_string(manifest)?;
+                        let kinds = if in_place(i) { &kinds[..] } else { manifest::KINDS };
+                        let mut rewritten = rewrite(package, &contents, kinds)?;
+                        if !in_place(i) {
+                            rewritten = manifest::add_workspace(&manifest::standalone(&rewritten, package.root())?)?;
                         }
+                        Ok((contents, rewritten))
+                    })
+                    .collect::<CargoResult<Vec<_>>>();
+                let rewritten = rewritten?;
+                interrupt::check()?;
+
+                for (&(i, ref manifest, copied), (contents, rewritten)) in pending.iter().zip(rewritten) {
+                    let package = &graph.nodes[i];
+                    // A symlink could have been copied in since everything was checked
+                    if !sandbox.contains(manifest) {
+                        return Err(errors::error(Code::OutsideSandbox, format!(
+                            "{} leads outside of {}\nRolled back",
+                            manifest.display(), describe(&sandbox)
+                        )));
+                    }
+                    if in_place(i) {
+                        state.remember(manifest, &contents);
+                    }
+                    // Copies made in this run are removed entirely on rollback,
+                    // so their original manifests don't need to be remembered
+                    if !copied {
+                        rollback.manifest(manifest.clone(), contents);
+                    }
+                    fs::write(manifest, rewritten)?;
+                    journal.record_rewrite(manifest)?;
+                    events::emit(&Event::ManifestRewritten {
+                        package_id: &package.id,
+                        manifest
+                    });
+                    if in_place(i) {
+                        state.rewrote(manifest)?;
+                    } else {
+                        let dest = manifest.parent().expect("Manifest path didn't have parent");
+                        let source = match vendor::vendored_path(&config, package)? {
+                            Some(path) => path,
+                            None => package.root().to_path_buf()
+                        };
+                        journal.record_copy(dest)?;
+                        state.record(&package.dir_name(), state::Origin::of(package), &source, dest)?;
                     }
                 }
-                fs::write(manifest, toml::to_string_pretty(&parsed)?)?;
-
-                cache.insert(package.package_id());
             }
-        }
-        if let Some(name) = name {
-            if let Some(entry) = stack.last_mut() {
-                let path = basedir.join(&*name);
-                entry.updated.get_or_insert_with(|| HashMap::with_capacity(4))
-                    .insert(name.to_string(), PackagePath::Path(path));
+            state.replace.extend(replace.clone());
+            for (name, annotation) in &annotations {
+                state.annotate(name, annotation.clone());
+            }
+            // The root manifest, Cargo.lock and deny.toml are only written from here on
+            for path in &[workspace.root().join("Cargo.toml"), workspace.root().join("Cargo.lock"), workspace.root().join("deny.toml")] {
+                let path = locate(path);
+                if path.exists() {
+                    let contents = fs::read_to_string(&path)?;
+                    rollback.manifest(path, contents);
+                } else {
+                    rollback.created(path);
+                }
             }
+            interrupt::check()?;
+            exclude_copies(&mut state)?;
+            interrupt::check()?;
+            update_lock(&mut state)?;
+            interrupt::check()?;
+            update_deny(&mut state)?;
+            state.save(&basedir)?;
+            Ok(())
+        };
+        patch()
+    };
+    if let Err(err) = result {
+        rollback.run();
+        journal.finish()?;
+        if interrupt::interrupted() {
+            return Err(errors::error(Code::Interrupted, "Rolled back"));
         }
+        return Err(err);
+    }
+    journal.finish()?;
+    print_shadow()?;
+
+    if let Some(relax) = relax_version {
+        versions::print_loosened(&mut config.shell(), &violations, relax == Relax::Drop)?;
     }
 
     Ok(())
 }
-fn copy<P1, P2>(src: P1, dst: P2) -> io::Result<()>
-    where P1: AsRef<Path>,
-          P2: AsRef<Path>
-{
-    let src = src.as_ref();
-    let dst = dst.as_ref();
-    debug_assert!(!dst.exists());
-
-    if src.is_dir() {
-        fs::create_dir(dst)?;
-        for entry in fs::read_dir(src)? {
-            let path = entry?.path();
-            copy(&path, dst.join(&path.strip_prefix(src).unwrap()))?;
-        }
-    } else {
-        fs::copy(src, dst)?;
+
+/// The packages whose dependencies are patched: the workspace member called
+/// `name`, the current one, or every member of a virtual workspace
+fn roots<'a>(workspace: &'a Workspace, name: Option<&str>) -> CargoResult<Vec<&'a Package>> {
+    let name = match name {
+        Some(name) => name,
+        None if workspace.is_virtual() => return Ok(workspace.members().collect()),
+        None => return Ok(vec![workspace.current()?])
+    };
+    if let Some(package) = workspace.members().find(|member| member.name().as_str() == name) {
+        return Ok(vec![package]);
+    }
+    let members: Vec<String> = workspace.members().map(|member| member.name().to_string()).collect();
+    Err(errors::error(Code::MemberNotFound, format!("{:?} isn't one of {}", name, members.join(", "))))
+}
+
+/// A replacement's URL, target and selector, as given
+type Given = (String, Option<String>, selector::Selector);
+/// Remember where each replacement was given, and handle a crate given
+/// different replacements in two places, which fails unless `on_conflict`
+/// says the last one wins
+fn check_conflict(
+    shell: &mut Shell,
+    given: &mut HashMap<String, (String, Given)>,
+    on_conflict: OnConflict,
+    name: &str,
+    origin: String,
+    spec: Given
+) -> CargoResult<()> {
+    if let Some((previous_origin, previous)) = given.get(name) {
+        if *previous != spec {
+            let describe = |spec: &Given| match spec.1 {
+                Some(ref cfg) => format!("{}{} [{}]", spec.0, spec.2, cfg),
+                None => format!("{}{}", spec.0, spec.2)
+            };
+            match on_conflict {
+                OnConflict::Error => return Err(errors::error(Code::ConflictingReplacements, format!(
+                    "{} is replaced with {} in {}\n{} is replaced with {} in {}\n\
+                     Remove one of them, or pass --on-conflict override to use the last one",
+                    name, describe(previous), previous_origin,
+                    name, describe(&spec), origin
+                ))),
+                OnConflict::Override => shell.status("Overriding", format!(
+                    "{} from {} with {} from {}",
+                    name, previous_origin, describe(&spec), origin
+                ))?
+            }
+        }
     }
+    given.insert(name.to_string(), (origin, spec));
     Ok(())
 }
+
+/// Ask a yes or no question, defaulting to no
+fn confirm(question: &str) -> io::Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
@@ -0,0 +1,1037 @@
+use cargo::{CargoResult, core::Workspace, util::Config};
+use errors::{self, Code};
+use semver::{Version, VersionReq};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf}
+};
+use toml;
+use toml_edit::{self, Array, Document, InlineTable, Item, Table, Value};
+use urls;
+
+pub enum PackagePath<'a> {
+    Git(&'a str),
+    Path(PathBuf),
+    /// Back to the registry, at this version or a compatible one
+    Registry(Version),
+    /// A fork published under another name, and the version requirement on it
+    Renamed(&'a str, &'a str)
+}
+
+impl<'a> PackagePath<'a> {
+    /// Where dependents of a crate are pointed to for a replacement given like with --replace
+    pub fn replacement(url: &'a str) -> Self {
+        match urls::local_path(url) {
+            Some(path) => PackagePath::Path(path.to_path_buf()),
+            None => match urls::renamed(url) {
+                Some((package, req)) => PackagePath::Renamed(package, req),
+                None => PackagePath::Git(url)
+            }
+        }
+    }
+}
+
+/// Parse a manifest for editing. Dotted keys like `foo.version = "1"` can't
+/// be read yet, so a dependency declared with them is pointed out instead of
+/// failing with a bare syntax error.
+fn parse(contents: &str) -> CargoResult<Document> {
+    let err = match contents.parse::<Document>() {
+        Ok(document) => return Ok(document),
+        Err(err) => err
+    };
+    let mut header = String::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            header = line.trim_matches('[').split(']').next().unwrap_or("").to_string();
+            continue;
+        }
+        let key = match line.find('=') {
+            Some(end) if !line.starts_with('#') => line[..end].trim(),
+            _ => continue
+        };
+        let dotted = key.split(|c| c == '"' || c == '\'').enumerate()
+            .any(|(n, part)| n % 2 == 0 && part.contains('.'));
+        if dotted && format!("{}.{}", header, key).contains("dependencies") {
+            return Err(errors::error(Code::InvalidDependency, format!(
+                "line {}: dotted keys like `{}` aren't supported yet, write the dependency as a table instead",
+                i + 1, key
+            )));
+        }
+    }
+    Err(err.into())
+}
+
+/// The dependency tables, and the kind of dependencies cargo calls the ones in them
+const TABLES: &[(&str, &str)] = &[("dependencies", "normal"), ("dev-dependencies", "dev"), ("build-dependencies", "build")];
+/// Every kind of dependency
+pub const KINDS: &[&str] = &["normal", "dev", "build"];
+
+/// The `[workspace.dependencies]` of the workspace root, which members can
+/// inherit from with `foo = { workspace = true }`, and likewise its
+/// `[workspace.package]` fields and `[workspace.lints]`
+#[derive(Default)]
+pub struct Inherited {
+    dependencies: BTreeMap<String, toml::Value>,
+    package: BTreeMap<String, toml::Value>,
+    lints: BTreeMap<String, toml::Value>,
+    /// The directory of the workspace root, which inherited paths are relative to
+    root: PathBuf
+}
+impl Inherited {
+    pub fn read(root_manifest: &Path) -> CargoResult<Self> {
+        let parsed: toml::Value = toml::from_str(&fs::read_to_string(root_manifest)?)?;
+        let table = |key: &str| parsed.get("workspace")
+            .and_then(|workspace| workspace.get(key))
+            .and_then(|table| table.as_table())
+            .map(|table| table.iter().map(|(key, value)| (key.clone(), value.clone())).collect())
+            .unwrap_or_default();
+        let root = root_manifest.parent().expect("Manifest path didn't have parent").to_path_buf();
+        // Paths are relative to the workspace root, not to the members inheriting them
+        let mut dependencies: BTreeMap<String, toml::Value> = table("dependencies");
+        for dependency in dependencies.values_mut() {
+            if let Some(dependency) = dependency.as_table_mut() {
+                let absolute = dependency.get("path")
+                    .and_then(|path| path.as_str())
+                    .map(|path| root.join(path).to_string_lossy().into_owned());
+                if let Some(absolute) = absolute {
+                    dependency.insert(String::from("path"), toml::Value::String(absolute));
+                }
+            }
+        }
+        Ok(Self {
+            dependencies,
+            package: table("package"),
+            lints: table("lints"),
+            root
+        })
+    }
+    /// The workspace the package in `dir` belongs to, found like cargo does,
+    /// by looking for the first manifest with a `[workspace]` from there up
+    fn find(dir: &Path) -> CargoResult<Option<Self>> {
+        let mut dir = Some(dir);
+        while let Some(current) = dir {
+            let manifest = current.join("Cargo.toml");
+            if manifest.is_file() {
+                let parsed: toml::Value = toml::from_str(&fs::read_to_string(&manifest)?)?;
+                if parsed.get("workspace").is_some() {
+                    return Self::read(&manifest).map(Some);
+                }
+            }
+            dir = current.parent();
+        }
+        Ok(None)
+    }
+}
+
+/// Load a workspace, failing with UnsupportedManifest if a member uses
+/// what's newer than the cargo this is built on, workspace inheritance and
+/// `[lints]`. That cargo rejects some of it with a bare parse error, and
+/// silently reads `foo = { workspace = true }` as any version of `foo`.
+pub fn workspace<'a>(manifest: &Path, config: &'a Config) -> CargoResult<Workspace<'a>> {
+    match Workspace::new(manifest, config) {
+        Ok(workspace) => {
+            for member in workspace.members() {
+                check_supported(member.manifest_path())?;
+            }
+            Ok(workspace)
+        },
+        Err(err) => {
+            // The members aren't known, so look at the ones listed without globs
+            check_supported(manifest)?;
+            let parsed: toml::Value = toml::from_str(&fs::read_to_string(manifest)?)?;
+            let members = parsed.get("workspace")
+                .and_then(|workspace| workspace.get("members"))
+                .and_then(|members| members.as_array())
+                .map(|members| members.iter().filter_map(|member| member.as_str()).collect())
+                .unwrap_or_else(Vec::new);
+            let root = manifest.parent().expect("Manifest path didn't have parent");
+            for member in members.into_iter().filter(|member| !member.contains(|c| c == '*' || c == '?' || c == '[')) {
+                let member = root.join(member).join("Cargo.toml");
+                if member.is_file() {
+                    check_supported(&member)?;
+                }
+            }
+            Err(err)
+        }
+    }
+}
+/// Fail with UnsupportedManifest if the manifest inherits from its workspace
+/// or has `[lints]`
+fn check_supported(path: &Path) -> CargoResult<()> {
+    let parsed: toml::Value = match toml::from_str(&fs::read_to_string(path)?) {
+        Ok(parsed) => parsed,
+        Err(_) => return Ok(())
+    };
+    let inherits = |value: &toml::Value| value.get("workspace").and_then(|workspace| workspace.as_bool()) == Some(true);
+    let mut found = None;
+    if parsed.get("lints").is_some() {
+        found = Some(String::from("[lints]"));
+    }
+    if let Some(package) = parsed.get("package").and_then(|package| package.as_table()) {
+        if let Some((key, _)) = package.iter().find(|&(_, value)| inherits(value)) {
+            found = Some(format!("{}.workspace = true", key));
+        }
+    }
+    let platforms = parsed.get("target")
+        .and_then(|target| target.as_table())
+        .map(|target| target.values().collect())
+        .unwrap_or_else(Vec::new);
+    for tables in Some(&parsed).into_iter().chain(platforms) {
+        for &(table, _) in TABLES {
+            let deps = match tables.get(table).and_then(|deps| deps.as_table()) {
+                Some(deps) => deps,
+                None => continue
+            };
+            if let Some((name, _)) = deps.iter().find(|&(_, dep)| inherits(dep)) {
+                found = Some(format!("{} = {{ workspace = true }}", name));
+            }
+        }
+    }
+    match found {
+        Some(found) => Err(errors::error(Code::UnsupportedManifest, format!(
+            "{} uses `{}`, which the version of cargo cargo-patch is built on can't load yet",
+            path.display(),
+            found
+        ))),
+        None => Ok(())
+    }
+}
+
+/// Point the dependencies in `replaces` to their new location, in the tables
+/// of the given `kinds`. Replacements listed in `targets` are restricted to
+/// the `[target.<cfg>]` tables of that cfg. Everything but the touched
+/// dependencies, including comments and the order of tables, is kept as it is.
+/// Touched dependencies inherited from the workspace get a spec of their own.
+/// Fails with InvalidDependency if a touched dependency is neither a string nor a table.
+pub fn rewrite(
+    contents: &str,
+    id: &str,
+    kinds: &[&str],
+    replaces: &HashMap<String, PackagePath>,
+    targets: &HashMap<String, String>,
+    loosened: &HashMap<(String, String), Option<VersionReq>>,
+    inherited: &Inherited
+) -> CargoResult<String> {
+    let mut document = parse(contents)?;
+    {
+        let root = document.as_table_mut();
+        rewrite_tables(root, id, kinds, replaces, loosened, inherited, |key| !targets.contains_key(key))?;
+        let platforms = if root.contains_key("target") {
+            root.entry("target").as_table_mut()
+        } else {
+            None
+        };
+        if let Some(platforms) = platforms {
+            let cfgs: Vec<String> = platforms.iter().map(|(cfg, _)| cfg.to_string()).collect();
+            for cfg in cfgs {
+                let applies = |key: &str| match targets.get(key) {
+                    Some(target) => same_cfg(target, &cfg),
+                    None => true
+                };
+                if let Some(tables) = platforms.entry(&cfg).as_table_mut() {
+                    rewrite_tables(tables, id, kinds, replaces, loosened, inherited, applies)?;
+                }
+            }
+        }
+    }
+    Ok(document.to_string())
+}
+/// Point the entries for the replaced crates in the manifest's `[patch]` and
+/// `[replace]` tables to the replacements too, so they agree with the
+/// rewritten dependencies instead of being left unused
+pub fn merge_overrides(contents: &str, replaces: &HashMap<String, PackagePath>) -> CargoResult<String> {
+    let mut document = parse(contents)?;
+    {
+        let root = document.as_table_mut();
+        let registries = if root.contains_key("patch") {
+            root.entry("patch").as_table_mut()
+        } else {
+            None
+        };
+        if let Some(registries) = registries {
+            let names: Vec<String> = registries.iter().map(|(registry, _)| registry.to_string()).collect();
+            for registry in names {
+                if let Some(table) = registries.entry(&registry).as_table_mut() {
+                    merge_table(table, replaces, |key| key);
+                }
+            }
+        }
+        let replace = if root.contains_key("replace") {
+            root.entry("replace").as_table_mut()
+        } else {
+            None
+        };
+        if let Some(replace) = replace {
+            // Keyed by package ID specs, like foo:1.2.3
+            merge_table(replace, replaces, |key| key.split(':').next().unwrap_or(key));
+        }
+    }
+    Ok(document.to_string())
+}
+fn merge_table<F>(table: &mut Table, replaces: &HashMap<String, PackagePath>, name_of: F)
+    where F: Fn(&str) -> &str
+{
+    let names: Vec<(String, String)> = table.iter()
+        .map(|(key, entry)| (key.to_string(), package_name(entry).unwrap_or_else(|| name_of(key)).to_string()))
+        .collect();
+    for (key, name) in names {
+        if let Some(value) = replaces.get(&name) {
+            // The replacement's own version is used, whatever the entry asked for
+            match table.entry(&key) {
+                Item::Table(inner) => change_path(inner, value, Some(&None)),
+                Item::Value(Value::InlineTable(inner)) => change_path(inner, value, Some(&None)),
+                _ => ()
+            }
+        }
+    }
+}
+/// Add `[patch.<source>]` entries pointing each crate to its replacement,
+/// given as the source it's patched in, the crate name and the replacement.
+/// Existing entries for those crates are replaced.
+pub fn add_patches(contents: &str, entries: &[(String, String, PackagePath)]) -> CargoResult<String> {
+    let mut document = parse(contents)?;
+    {
+        let patch = document.as_table_mut().entry("patch");
+        if patch.is_none() {
+            *patch = Item::Table(Table::new());
+        }
+        if let Some(sources) = patch.as_table_mut() {
+            for (source, name, value) in entries {
+                let table = sources.entry(source);
+                if table.is_none() {
+                    *table = Item::Table(Table::new());
+                }
+                if let Some(table) = table.as_table_mut() {
+                    let mut inline = InlineTable::default();
+                    change_path(&mut inline, value, Some(&None));
+                    inline.fmt();
+                    *table.entry(name) = Item::Value(Value::InlineTable(inline));
+                }
+            }
+        }
+    }
+    Ok(document.to_string())
+}
+/// Make the manifest the root of a workspace of its own with an empty
+/// `[workspace]` table, unless it already is one. Copies inside the
+/// workspace would be taken for members of it otherwise.
+pub fn add_workspace(contents: &str) -> CargoResult<String> {
+    let mut document = parse(contents)?;
+    {
+        let root = document.as_table_mut();
+        if !root.contains_key("workspace") {
+            *root.entry("workspace") = Item::Table(Table::new());
+        }
+    }
+    Ok(document.to_string())
+}
+/// The fields of `[package]` that are paths, relative to the workspace root when inherited
+const PATH_FIELDS: &[&str] = &["license-file", "readme"];
+
+/// Give a package copied out of its workspace the `[package]` fields and
+/// `[lints]` it inherits from there, like `version.workspace = true`, so it
+/// builds on its own. `dir` is where the package was copied from. Left as it
+/// is if it doesn't inherit anything, or its workspace can't be found.
+pub fn standalone(contents: &str, dir: &Path) -> CargoResult<String> {
+    let mut document = parse(contents)?;
+    let (fields, lints) = inherited_fields(&mut document);
+    if fields.is_empty() && !lints {
+        return Ok(contents.to_string());
+    }
+    let inherited = match Inherited::find(dir)? {
+        Some(inherited) => inherited,
+        None => return Ok(contents.to_string())
+    };
+    let package: BTreeMap<String, toml::Value> = inherited.package.iter()
+        .map(|(key, value)| (key.clone(), match value.as_str() {
+            Some(path) if PATH_FIELDS.contains(&&**key) =>
+                toml::Value::String(inherited.root.join(path).to_string_lossy().into_owned()),
+            _ => value.clone()
+        }))
+        .collect();
+    fill_inherited(&mut document, &fields, lints, &package, &inherited.lints);
+    Ok(document.to_string())
+}
+/// Put back the manifest a registry package was published with,
+/// `Cargo.toml.orig`, in its copy in `dir`, so the copy can be compared with
+/// the upstream repository. What only works in that repository, inherited
+/// fields and dependencies, and path dependencies, is taken from the
+/// normalized manifest instead. Returns false if there's no original manifest.
+pub fn restore_original(dir: &Path) -> CargoResult<bool> {
+    let original = dir.join("Cargo.toml.orig");
+    if !original.exists() {
+        return Ok(false);
+    }
+    let normalized: toml::Value = toml::from_str(&fs::read_to_string(dir.join("Cargo.toml"))?)?;
+    let mut document = parse(&fs::read_to_string(&original)?)?;
+    let (fields, lints) = inherited_fields(&mut document);
+    let table = |value: Option<&toml::Value>| value
+        .and_then(|table| table.as_table())
+        .map(|table| table.iter().map(|(key, value)| (key.clone(), value.clone())).collect())
+        .unwrap_or_default();
+    fill_inherited(&mut document, &fields, lints, &table(normalized.get("package")), &table(normalized.get("lints")));
+    {
+        let root = document.as_table_mut();
+        restore_dependencies(root, Some(&normalized));
+        let platforms = if root.contains_key("target") {
+            root.entry("target").as_table_mut()
+        } else {
+            None
+        };
+        if let Some(platforms) = platforms {
+            let cfgs: Vec<String> = platforms.iter().map(|(cfg, _)| cfg.to_string()).collect();
+            for cfg in cfgs {
+                if let Some(tables) = platforms.entry(&cfg).as_table_mut() {
+                    restore_dependencies(tables, normalized.get("target").and_then(|target| target.get(&cfg)));
+                }
+            }
+        }
+    }
+    fs::write(dir.join("Cargo.toml"), document.to_string())?;
+    Ok(true)
+}
+/// Replace the dependencies of an original manifest that are inherited or
+/// have a path with their entries in the normalized manifest. Path
+/// dependencies without a version aren't published, so they're removed.
+fn restore_dependencies(tables: &mut Table, normalized: Option<&toml::Value>) {
+    for (table, _) in TABLES {
+        if !tables.contains_key(table) {
+            continue;
+        }
+        if let Some(deps) = tables.entry(table).as_table_mut() {
+            let keys: Vec<String> = deps.keys().into_iter()
+                .filter(|key| deps.field(key, "path").is_some()
+                    || deps.field(key, "workspace").and_then(|workspace| workspace.as_bool()) == Some(true))
+                .collect();
+            for key in keys {
+                let published = normalized
+                    .and_then(|normalized| normalized.get(*table))
+                    .and_then(|deps| deps.get(&key))
+                    .and_then(convert);
+                match published {
+                    Some(published) => *deps.entry(&key) = Item::Value(published),
+                    None => {
+                        deps.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+}
+/// The `[package]` fields of a manifest inherited from its workspace, and
+/// whether its `[lints]` are
+fn inherited_fields(document: &mut Document) -> (Vec<String>, bool) {
+    let root = document.as_table_mut();
+    let package = if root.contains_key("package") {
+        root.entry("package").as_table()
+    } else {
+        None
+    };
+    let fields: Vec<String> = package
+        .map(|package| package.iter().filter(|&(_, field)| is_inherited(field)).map(|(key, _)| key.to_string()).collect())
+        .unwrap_or_default();
+    let lints = root.contains_key("lints") && is_inherited(root.entry("lints"));
+    (fields, lints)
+}
+/// Replace the inherited `fields` and `[lints]` with the values they inherit
+fn fill_inherited(
+    document: &mut Document,
+    fields: &[String],
+    lints: bool,
+    package: &BTreeMap<String, toml::Value>,
+    inherited_lints: &BTreeMap<String, toml::Value>
+) {
+    let root = document.as_table_mut();
+    {
+        let table = if fields.is_empty() {
+            None
+        } else {
+            root.entry("package").as_table_mut()
+        };
+        if let Some(table) = table {
+            for key in fields {
+                if let Some(value) = package.get(key).and_then(convert) {
+                    *table.entry(key) = Item::Value(value);
+                }
+            }
+        }
+    }
+    if lints {
+        let mut table = Table::new();
+        for (tool, levels) in inherited_lints {
+            if let Some(levels) = convert(levels) {
+                *table.entry(tool) = Item::Value(levels);
+            }
+        }
+        *root.entry("lints") = Item::Table(table);
+    }
+}
+/// Whether a field or dependency is declared with `workspace = true`
+fn is_inherited(item: &Item) -> bool {
+    let workspace = match item {
+        Item::Table(table) => DependencyTable::get(table, "workspace"),
+        Item::Value(Value::InlineTable(table)) => DependencyTable::get(table, "workspace"),
+        _ => None
+    };
+    workspace.and_then(|workspace| workspace.as_bool()) == Some(true)
+}
+/// Convert a value read with the toml crate into one to insert with toml_edit,
+/// with tables written inline
+fn convert(value: &toml::Value) -> Option<Value> {
+    match value {
+        toml::Value::Table(table) => {
+            let mut inline = InlineTable::default();
+            for (key, value) in table {
+                inline.get_or_insert(key, convert(value)?);
+            }
+            inline.fmt();
+            Some(Value::InlineTable(inline))
+        },
+        // Parsed back from TOML, since the two crates' values don't convert into each other
+        value => value.to_string().parse().ok()
+    }
+}
+/// Add `dir` to the `exclude` list of the manifest's `[workspace]`, if it
+/// isn't there yet. Returns None if the manifest isn't a workspace root.
+pub fn exclude(contents: &str, dir: &str) -> CargoResult<Option<String>> {
+    let mut document = parse(contents)?;
+    let is_root = {
+        let root = document.as_table_mut();
+        let workspace = if root.contains_key("workspace") {
+            root.entry("workspace").as_table_mut()
+        } else {
+            None
+        };
+        match workspace {
+            Some(workspace) => {
+                let exclude = workspace.entry("exclude");
+                if !exclude.as_value().map(|exclude| exclude.as_array().is_some()).unwrap_or(false) {
+                    *exclude = toml_edit::value(Array::default());
+                }
+                if let Some(exclude) = exclude.as_value_mut().and_then(Value::as_array_mut) {
+                    if !exclude.iter().any(|other| other.as_str().map(|other| other.trim_right_matches('/')) == Some(dir)) {
+                        exclude.push(dir);
+                    }
+                }
+                true
+            },
+            None => false
+        }
+    };
+    Ok(if is_root { Some(document.to_string()) } else { None })
+}
+/// Copy the dependencies pointing to a path or git repository in the
+/// manifest `old` over to the manifest `new`, for the dependencies `new` has
+/// too. If `old` is a workspace of its own, `new` is made one as well.
+pub fn carry_over(old: &str, new: &str) -> CargoResult<String> {
+    let old: toml::Value = toml::from_str(old)?;
+    let mut rewritten = Vec::new();
+    {
+        let mut collect = |tables: &toml::Value| {
+            let tables = TABLES.iter().filter_map(|(table, _)| tables.get(*table).and_then(|deps| deps.as_table()));
+            for deps in tables {
+                for (name, dep) in deps {
+                    if dep.get("path").is_some() || dep.get("git").is_some() {
+                        rewritten.push((name.clone(), dep.clone()));
+                    }
+                }
+            }
+        };
+        collect(&old);
+        if let Some(targets) = old.get("target").and_then(|target| target.as_table()) {
+            for tables in targets.values() {
+                collect(tables);
+            }
+        }
+    }
+    let mut document = parse(new)?;
+    {
+        let root = document.as_table_mut();
+        each_table(root, &mut |deps: &mut Dependencies| {
+            let keys = deps.keys();
+            for (name, dep) in rewritten.iter().filter(|(name, _)| keys.contains(name)) {
+                if let Some(value) = convert(dep) {
+                    deps.replace(name, value);
+                }
+            }
+        });
+        let workspace = old.get("workspace").and_then(|workspace| workspace.as_table());
+        if let Some(workspace) = workspace.filter(|_| !root.contains_key("workspace")) {
+            let mut table = Table::new();
+            for (key, value) in workspace {
+                if let Some(value) = convert(value) {
+                    *table.entry(key) = Item::Value(value);
+                }
+            }
+            *root.entry("workspace") = Item::Table(table);
+        }
+    }
+    Ok(document.to_string())
+}
+/// Point path dependencies on `name` at `old` to `new`. Returns None if
+/// nothing pointed to `old`.
+pub fn repoint(contents: &str, name: &str, old: &Path, new: &Path) -> CargoResult<Option<String>> {
+    let mut document = parse(contents)?;
+    let mut changed = false;
+    each_table(document.as_table_mut(), &mut |deps: &mut Dependencies| {
+        let points_to_old = deps.field(name, "path")
+            .and_then(|path| path.as_str())
+            .map(|path| Path::new(path) == old)
+            .unwrap_or(false);
+        if points_to_old && deps.rewrite(name, &PackagePath::Path(new.to_path_buf()), None, None) {
+            changed = true;
+        }
+    });
+    Ok(if changed { Some(document.to_string()) } else { None })
+}
+/// Call `f` with every dependency table of a manifest, including the ones under `[target]`
+fn each_table(tables: &mut Table, f: &mut FnMut(&mut Dependencies)) {
+    for (table, _) in TABLES {
+        if !tables.contains_key(table) {
+            continue;
+        }
+        match tables.entry(table) {
+            Item::Table(deps) => f(deps),
+            Item::Value(Value::InlineTable(deps)) => f(deps),
+            _ => ()
+        }
+    }
+    let platforms = if tables.contains_key("target") {
+        tables.entry("target").as_table_mut()
+    } else {
+        None
+    };
+    if let Some(platforms) = platforms {
+        let cfgs: Vec<String> = platforms.iter().map(|(cfg, _)| cfg.to_string()).collect();
+        for cfg in cfgs {
+            if let Some(platform) = platforms.entry(&cfg).as_table_mut() {
+                each_table(platform, f);
+            }
+        }
+    }
+}
+/// Compare two target specifications, ignoring whitespace
+pub fn same_cfg(a: &str, b: &str) -> bool {
+    a.chars().filter(|c| !c.is_whitespace()).eq(b.chars().filter(|c| !c.is_whitespace()))
+}
+fn rewrite_tables<F>(
+    tables: &mut Table,
+    id: &str,
+    kinds: &[&str],
+    replaces: &HashMap<String, PackagePath>,
+    loosened: &HashMap<(String, String), Option<VersionReq>>,
+    inherited: &Inherited,
+    applies: F
+) -> CargoResult<()>
+    where F: Fn(&str) -> bool
+{
+    for (table, kind) in TABLES {
+        if !kinds.contains(kind) || !tables.contains_key(table) {
+            continue;
+        }
+        match tables.entry(table) {
+            Item::Table(deps) => rewrite_dependencies(deps, id, replaces, loosened, inherited, &applies)?,
+            // Written on one line, like `dependencies = { foo = "1" }`
+            Item::Value(Value::InlineTable(deps)) => rewrite_dependencies(deps, id, replaces, loosened, inherited, &applies)?,
+            _ => ()
+        }
+    }
+    Ok(())
+}
+fn rewrite_dependencies<T, F>(
+    deps: &mut T,
+    id: &str,
+    replaces: &HashMap<String, PackagePath>,
+    loosened: &HashMap<(String, String), Option<VersionReq>>,
+    inherited: &Inherited,
+    applies: &F
+) -> CargoResult<()>
+    where T: Dependencies,
+          F: Fn(&str) -> bool
+{
+    // Renamed dependencies are matched by the name of the package, and keep their key
+    let names: Vec<(String, String, Option<&toml::Value>)> = deps.keys().into_iter()
+        .map(|key| {
+            let is_inherited = deps.field(&key, "workspace").and_then(|workspace| workspace.as_bool()) == Some(true);
+            let root = if is_inherited { inherited.dependencies.get(&key) } else { None };
+            let name = deps.field(&key, "package").and_then(|package| package.as_str()).map(String::from)
+                .or_else(|| root.and_then(|root| root.get("package")).and_then(|package| package.as_str()).map(String::from))
+                .unwrap_or_else(|| key.clone());
+            (key, name, root)
+        })
+        .collect();
+    for (key, name, root) in names {
+        let value = match replaces.get(&name) {
+            Some(value) if applies(&name) => value,
+            _ => continue
+        };
+        let req = loosened.get(&(id.to_string(), name));
+        if !deps.rewrite(&key, value, req, root) {
+            return Err(errors::error(Code::InvalidDependency, format!("Dependency {:?} is not a string nor a table", key)));
+        }
+    }
+    Ok(())
+}
+/// The two ways a table of dependencies can be written, `[dependencies]`
+/// (with `[dependencies.foo]` sub-tables) and `dependencies = { ... }`
+trait Dependencies {
+    fn keys(&self) -> Vec<String>;
+    /// A key of the dependency `key`, if it's written as a table
+    fn field(&self, key: &str, field: &str) -> Option<&Value>;
+    /// Point the dependency `key` to `value`, giving it a spec of its own if
+    /// it's inherited from `root`. Returns false if it's not a string nor a table.
+    fn rewrite(&mut self, key: &str, value: &PackagePath, relaxed: Option<&Option<VersionReq>>, root: Option<&toml::Value>) -> bool;
+    /// Replace the whole dependency `key` with `value`
+    fn replace(&mut self, key: &str, value: Value);
+}
+impl Dependencies for Table {
+    fn keys(&self) -> Vec<String> {
+        self.iter().map(|(key, _)| key.to_string()).collect()
+    }
+    fn field(&self, key: &str, field: &str) -> Option<&Value> {
+        match self.iter().find(|(other, _)| *other == key).map(|(_, dep)| dep) {
+            Some(Item::Table(dep)) => DependencyTable::get(dep, field),
+            Some(Item::Value(Value::InlineTable(dep))) => DependencyTable::get(dep, field),
+            _ => None
+        }
+    }
+    fn rewrite(&mut self, key: &str, value: &PackagePath, relaxed: Option<&Option<VersionReq>>, root: Option<&toml::Value>) -> bool {
+        match self.entry(key) {
+            Item::Table(dep) => {
+                uninherit(dep, root);
+                change_path(dep, value, relaxed);
+                true
+            },
+            Item::Value(dep) => rewrite_value(dep, value, relaxed, root),
+            _ => false
+        }
+    }
+    fn replace(&mut self, key: &str, value: Value) {
+        *self.entry(key) = Item::Value(value);
+    }
+}
+impl Dependencies for InlineTable {
+    fn keys(&self) -> Vec<String> {
+        self.iter().map(|(key, _)| key.to_string()).collect()
+    }
+    fn field(&self, key: &str, field: &str) -> Option<&Value> {
+        match self.iter().find(|(other, _)| *other == key).map(|(_, dep)| dep) {
+            Some(Value::InlineTable(dep)) => DependencyTable::get(dep, field),
+            _ => None
+        }
+    }
+    fn rewrite(&mut self, key: &str, value: &PackagePath, relaxed: Option<&Option<VersionReq>>, root: Option<&toml::Value>) -> bool {
+        match self.get_mut(key) {
+            Some(dep) => rewrite_value(dep, value, relaxed, root),
+            None => false
+        }
+    }
+    fn replace(&mut self, key: &str, value: Value) {
+        self.remove(key);
+        self.get_or_insert(key, value);
+    }
+}
+/// Point a dependency written as a value, either a version or an inline table, to `value`
+fn rewrite_value(dep: &mut Value, value: &PackagePath, relaxed: Option<&Option<VersionReq>>, root: Option<&toml::Value>) -> bool {
+    if let Some(version) = dep.as_str().map(String::from) {
+        let mut inline = InlineTable::default();
+        inline.set("version", &version);
+        change_path(&mut inline, value, relaxed);
+        inline.fmt();
+        *dep = Value::InlineTable(inline);
+        return true;
+    }
+    match dep {
+        Value::InlineTable(inner) => {
+            uninherit(inner, root);
+            change_path(inner, value, relaxed);
+            true
+        },
+        _ => false
+    }
+}
+/// The package a dependency renamed with `package = "..."` refers to
+fn package_name(dep: &Item) -> Option<&str> {
+    match dep {
+        Item::Table(table) => table.iter()
+            .find(|(key, _)| *key == "package")
+            .and_then(|(_, package)| package.as_str()),
+        Item::Value(Value::InlineTable(table)) => table.iter()
+            .find(|(key, _)| *key == "package")
+            .and_then(|(_, package)| package.as_str()),
+        _ => None
+    }
+}
+/// Returns true if the manifest has a dependency on the package `name`, in
+/// any table, even if it's renamed
+pub fn depends_on(contents: &str, name: &str) -> CargoResult<bool> {
+    let parsed: toml::Value = toml::from_str(contents)?;
+    let has = |tables: &toml::Value| TABLES.iter()
+        .filter_map(|(table, _)| tables.get(*table).and_then(|deps| deps.as_table()))
+        .any(|deps| deps.iter().any(|(key, dep)| {
+            dep.get("package").and_then(|package| package.as_str()).unwrap_or(key) == name
+        }));
+    Ok(has(&parsed) || parsed.get("target")
+        .and_then(|target| target.as_table())
+        .map(|targets| targets.values().any(|tables| has(tables)))
+        .unwrap_or(false))
+}
+/// The two ways a dependency can be written as a table, `[dependencies.foo]` and `foo = { ... }`
+trait DependencyTable {
+    fn get(&self, key: &str) -> Option<&Value>;
+    fn set_value(&mut self, key: &str, value: Value);
+    fn unset(&mut self, key: &str);
+
+    fn set(&mut self, key: &str, value: &str) {
+        self.set_value(key, Value::from(value));
+    }
+}
+impl DependencyTable for Table {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.iter().find(|(other, _)| *other == key).and_then(|(_, value)| value.as_value())
+    }
+    fn set_value(&mut self, key: &str, value: Value) {
+        *self.entry(key) = Item::Value(value);
+    }
+    fn unset(&mut self, key: &str) {
+        self.remove(key);
+    }
+}
+impl DependencyTable for InlineTable {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.iter().find(|(other, _)| *other == key).map(|(_, value)| value)
+    }
+    fn set_value(&mut self, key: &str, value: Value) {
+        self.remove(key);
+        self.get_or_insert(key, value);
+    }
+    fn unset(&mut self, key: &str) {
+        self.remove(key);
+    }
+}
+/// The keys of `[workspace.dependencies]` entries a member inherits as they
+/// are. Features are added to instead.
+const INHERITED_KEYS: &[&str] = &[
+    "version", "package", "default-features",
+    "registry", "registry-index", "git", "branch", "tag", "rev", "path"
+];
+
+/// Turn a dependency inherited from the workspace into one of its own,
+/// keeping everything it had in `[workspace.dependencies]`
+fn uninherit<T: DependencyTable>(table: &mut T, root: Option<&toml::Value>) {
+    let root = match root {
+        Some(root) => root,
+        None => return
+    };
+    table.unset("workspace");
+    // Written as `foo = "1.0"`
+    if let Some(version) = root.as_str() {
+        if table.get("version").is_none() {
+            table.set("version", version);
+        }
+        return;
+    }
+    for key in INHERITED_KEYS {
+        if table.get(key).is_none() {
+            if let Some(value) = root.get(*key).and_then(convert) {
+                table.set_value(key, value);
+            }
+        }
+    }
+    let mut features: Vec<toml::Value> = root.get("features")
+        .and_then(|features| features.as_array())
+        .cloned()
+        .unwrap_or_default();
+    if let Some(own) = table.get("features").and_then(|features| features.as_array()) {
+        for feature in own.iter().filter_map(|feature| feature.as_str()) {
+            let feature = toml::Value::String(feature.to_string());
+            if !features.contains(&feature) {
+                features.push(feature);
+            }
+        }
+    }
+    if !features.is_empty() {
+        if let Some(features) = convert(&toml::Value::Array(features)) {
+            table.set_value("features", features);
+        }
+    }
+}
+/// The keys of a dependency that say where it comes from. Only these are
+/// replaced, so `features`, `optional`, `default-features` and the rest are
+/// carried over as they are, and feature resolution stays the same. The
+/// version requirement is kept too, unless it's relaxed.
+const SOURCE_KEYS: &[&str] = &["path", "git", "branch", "tag", "rev", "registry", "registry-index"];
+
+/// Point a dependency to `value`. `relaxed` is the requirement to use instead
+/// of the current one, or None to drop it, if it's changed.
+fn change_path<T: DependencyTable>(table: &mut T, value: &PackagePath, relaxed: Option<&Option<VersionReq>>) {
+    for key in SOURCE_KEYS {
+        table.unset(key);
+    }
+    match relaxed {
+        Some(Some(req)) => table.set("version", &req.to_string()),
+        Some(None) => table.unset("version"),
+        None => ()
+    }
+    match value {
+        PackagePath::Path(path) => table.set("path", &path.to_string_lossy()),
+        PackagePath::Git(url) => {
+            let (url, reference) = urls::split_reference(url);
+            table.set("git", url);
+            if let Some((key, value)) = reference {
+                table.set(key, value);
+            }
+        }
+        PackagePath::Registry(version) => table.set("version", &version.to_string()),
+        PackagePath::Renamed(package, req) => {
+            table.set("package", package);
+            table.set("version", req);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewritten(contents: &str, replaces: Vec<(&str, PackagePath)>) -> CargoResult<toml::Value> {
+        let replaces = replaces.into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+        let contents = rewrite(contents, "app", KINDS, &replaces, &HashMap::new(), &HashMap::new(), &Inherited::default())?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    #[test]
+    fn rewrite_version() {
+        let parsed = rewritten(
+            "[dependencies]\nfoo = \"1.0\"\nbar = \"2\"\n",
+            vec![("foo", PackagePath::Git("https://github.com/me/foo?branch=dev"))]
+        ).unwrap();
+        let foo = &parsed["dependencies"]["foo"];
+        assert_eq!(foo["version"].as_str(), Some("1.0"));
+        assert_eq!(foo["git"].as_str(), Some("https://github.com/me/foo"));
+        assert_eq!(foo["branch"].as_str(), Some("dev"));
+        assert_eq!(parsed["dependencies"]["bar"].as_str(), Some("2"));
+    }
+
+    #[test]
+    fn rewrite_table() {
+        let parsed = rewritten(
+            "[dependencies.foo]\ngit = \"https://github.com/up/foo\"\nrev = \"abc\"\nfeatures = [\"x\"]\noptional = true\n",
+            vec![("foo", PackagePath::Path(PathBuf::from("/copies/foo")))]
+        ).unwrap();
+        let foo = parsed["dependencies"]["foo"].as_table().unwrap();
+        assert_eq!(foo["path"].as_str(), Some("/copies/foo"));
+        assert!(!foo.contains_key("git"));
+        assert!(!foo.contains_key("rev"));
+        assert_eq!(foo["features"].as_array().unwrap().len(), 1);
+        assert_eq!(foo["optional"].as_bool(), Some(true));
+    }
+
+    #[test]
+    fn rewrite_keeps_comments() {
+        let contents = "# the app\n[package]\nname = \"app\"\n\n# what it uses\n[dependencies]\nfoo = \"1.0\"\n";
+        let mut replaces = HashMap::new();
+        replaces.insert(String::from("foo"), PackagePath::Path(PathBuf::from("/copies/foo")));
+        let contents = rewrite(contents, "app", KINDS, &replaces, &HashMap::new(), &HashMap::new(), &Inherited::default()).unwrap();
+        assert!(contents.starts_with("# the app\n[package]\nname = \"app\"\n"));
+        assert!(contents.contains("\n# what it uses\n[dependencies]\n"));
+    }
+
+    #[test]
+    fn rewrite_renamed() {
+        let parsed = rewritten(
+            "[dependencies]\nmyfoo = { package = \"foo\", version = \"1\" }\nfoo = \"2\"\n",
+            vec![("foo", PackagePath::Path(PathBuf::from("/copies/foo")))]
+        ).unwrap();
+        assert_eq!(parsed["dependencies"]["myfoo"]["path"].as_str(), Some("/copies/foo"));
+        assert_eq!(parsed["dependencies"]["myfoo"]["package"].as_str(), Some("foo"));
+        assert_eq!(parsed["dependencies"]["foo"]["path"].as_str(), Some("/copies/foo"));
+    }
+
+    #[test]
+    fn rewrite_kinds() {
+        let mut replaces = HashMap::new();
+        replaces.insert(String::from("foo"), PackagePath::Path(PathBuf::from("/copies/foo")));
+        let contents = "[dependencies]\nfoo = \"1\"\n\n[dev-dependencies]\nfoo = \"1\"\n";
+        let contents = rewrite(contents, "app", &["normal"], &replaces, &HashMap::new(), &HashMap::new(), &Inherited::default()).unwrap();
+        let parsed: toml::Value = toml::from_str(&contents).unwrap();
+        assert_eq!(parsed["dependencies"]["foo"]["path"].as_str(), Some("/copies/foo"));
+        assert_eq!(parsed["dev-dependencies"]["foo"].as_str(), Some("1"));
+    }
+
+    #[test]
+    fn rewrite_target() {
+        let mut replaces = HashMap::new();
+        replaces.insert(String::from("foo"), PackagePath::Path(PathBuf::from("/copies/foo")));
+        let mut targets = HashMap::new();
+        targets.insert(String::from("foo"), String::from("cfg(windows)"));
+        let contents = "[dependencies]\nfoo = \"1\"\n\n[target.'cfg( windows )'.dependencies]\nfoo = \"1\"\n\n[target.'cfg(unix)'.dependencies]\nfoo = \"1\"\n";
+        let contents = rewrite(contents, "app", KINDS, &replaces, &targets, &HashMap::new(), &Inherited::default()).unwrap();
+        let parsed: toml::Value = toml::from_str(&contents).unwrap();
+        assert_eq!(parsed["dependencies"]["foo"].as_str(), Some("1"));
+        assert_eq!(parsed["target"]["cfg( windows )"]["dependencies"]["foo"]["path"].as_str(), Some("/copies/foo"));
+        assert_eq!(parsed["target"]["cfg(unix)"]["dependencies"]["foo"].as_str(), Some("1"));
+    }
+
+    #[test]
+    fn rewrite_loosened() {
+        let mut replaces = HashMap::new();
+        replaces.insert(String::from("foo"), PackagePath::Path(PathBuf::from("/copies/foo")));
+        replaces.insert(String::from("bar"), PackagePath::Path(PathBuf::from("/copies/bar")));
+        let req = VersionReq::parse(">= 1.0").unwrap();
+        let mut loosened = HashMap::new();
+        loosened.insert((String::from("app"), String::from("foo")), Some(req.clone()));
+        loosened.insert((String::from("app"), String::from("bar")), None);
+        loosened.insert((String::from("other"), String::from("foo")), None);
+        let contents = "[dependencies]\nfoo = \"=0.9\"\nbar = { version = \"2\" }\n";
+        let contents = rewrite(contents, "app", KINDS, &replaces, &HashMap::new(), &loosened, &Inherited::default()).unwrap();
+        let parsed: toml::Value = toml::from_str(&contents).unwrap();
+        assert_eq!(parsed["dependencies"]["foo"]["version"].as_str(), Some(&*req.to_string()));
+        assert!(!parsed["dependencies"]["bar"].as_table().unwrap().contains_key("version"));
+    }
+
+    #[test]
+    fn rewrite_invalid() {
+        let err = rewritten("[dependencies]\nfoo = 1\n", vec![("foo", PackagePath::Path(PathBuf::from("/copies/foo")))]);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn rewrite_inherited() {
+        let mut root = BTreeMap::new();
+        root.insert(String::from("version"), toml::Value::String(String::from("1.2")));
+        root.insert(String::from("default-features"), toml::Value::Boolean(false));
+        root.insert(String::from("features"), toml::Value::Array(vec![toml::Value::String(String::from("a"))]));
+        let mut inherited = Inherited::default();
+        inherited.dependencies.insert(String::from("foo"), toml::Value::Table(root));
+        inherited.dependencies.insert(String::from("bar"), toml::Value::String(String::from("0.3")));
+
+        let mut replaces = HashMap::new();
+        replaces.insert(String::from("foo"), PackagePath::Git("https://github.com/me/foo"));
+        replaces.insert(String::from("bar"), PackagePath::Git("https://github.com/me/bar"));
+        let contents = "[dependencies]\nfoo = { workspace = true, features = [\"b\"] }\n\n[dependencies.bar]\nworkspace = true\n";
+        let contents = rewrite(contents, "app", KINDS, &replaces, &HashMap::new(), &HashMap::new(), &inherited).unwrap();
+        let parsed: toml::Value = toml::from_str(&contents).unwrap();
+
+        let foo = parsed["dependencies"]["foo"].as_table().unwrap();
+        assert!(!foo.contains_key("workspace"));
+        assert_eq!(foo["version"].as_str(), Some("1.2"));
+        assert_eq!(foo["default-features"].as_bool(), Some(false));
+        assert_eq!(foo["git"].as_str(), Some("https://github.com/me/foo"));
+        let features: Vec<&str> = foo["features"].as_array().unwrap().iter().filter_map(|feature| feature.as_str()).collect();
+        assert_eq!(features, vec!["a", "b"]);
+
+        let bar = parsed["dependencies"]["bar"].as_table().unwrap();
+        assert!(!bar.contains_key("workspace"));
+        assert_eq!(bar["version"].as_str(), Some("0.3"));
+        assert_eq!(bar["git"].as_str(), Some("https://github.com/me/bar"));
+    }
+
+    #[test]
+    fn cfgs() {
+        assert!(same_cfg("cfg(windows)", "cfg( windows )"));
+        assert!(!same_cfg("cfg(windows)", "cfg(unix)"));
+    }
+}
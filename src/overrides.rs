@@ -0,0 +1,38 @@
+use cargo::CargoResult;
+use std::path::{Path, PathBuf};
+use toml_edit::{self, Array, Document, Value};
+
+/// The cargo configuration of the workspace, `.cargo/config.toml`, or the
+/// older `.cargo/config` if that's the one there is
+pub fn config_path(root: &Path) -> PathBuf {
+    let dir = root.join(".cargo");
+    let old = dir.join("config");
+    if old.is_file() && !dir.join("config.toml").exists() {
+        old
+    } else {
+        dir.join("config.toml")
+    }
+}
+
+/// Add the directories to the `paths` overrides in a cargo configuration,
+/// keeping the ones already there
+pub fn add_paths(contents: &str, dirs: &[&Path]) -> CargoResult<String> {
+    let mut document: Document = contents.parse()?;
+    {
+        let root = document.as_table_mut();
+        let is_array = root.contains_key("paths")
+            && root.entry("paths").as_value().map(|paths| paths.as_array().is_some()).unwrap_or(false);
+        if !is_array {
+            *root.entry("paths") = toml_edit::value(Array::default());
+        }
+        if let Some(paths) = root.entry("paths").as_value_mut().and_then(Value::as_array_mut) {
+            for dir in dirs {
+                let dir = dir.to_string_lossy();
+                if !paths.iter().any(|path| path.as_str() == Some(&*dir)) {
+                    paths.push(&*dir);
+                }
+            }
+        }
+    }
+    Ok(document.to_string())
+}
@@ -0,0 +1,67 @@
+use cargo::{
+    CargoResult,
+    core::{Package, SourceId},
+    sources::PathSource,
+    util::config::Config
+};
+use ignore;
+use interrupt;
+use std::{
+    collections::HashSet,
+    fs,
+    io,
+    path::{Path, PathBuf}
+};
+
+/// The files of the package in `root` that `cargo package` would ship,
+/// following its `include` and `exclude`, relative to `root`
+pub fn files(config: &Config, root: &Path) -> CargoResult<Vec<PathBuf>> {
+    let package = Package::for_path(&root.join("Cargo.toml"), config)?;
+    let source_id = SourceId::for_path(root)?;
+    let source = PathSource::new(root, &source_id, config);
+    Ok(source.list_files(&package)?
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect())
+}
+
+/// Copy the `files` of the package in `src` to `dst`
+pub fn copy(src: &Path, dst: &Path, files: &[PathBuf]) -> io::Result<()> {
+    fs::create_dir(dst)?;
+    for file in files {
+        interrupt::check()?;
+        let target = dst.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(src.join(file), &target)?;
+    }
+    Ok(())
+}
+
+/// The files in `src` that aren't in `files`, as patterns anchored to the
+/// package root like `/.travis.yml`, so comparing the copy with its source
+/// doesn't take them for files removed from the copy. Files the globs in
+/// `patterns` leave out are left out here too.
+pub fn left_out(src: &Path, files: &[PathBuf], patterns: &[String]) -> io::Result<Vec<String>> {
+    fn visit(root: &Path, path: &Path, files: &HashSet<&Path>, patterns: &[String], left_out: &mut Vec<String>) -> io::Result<()> {
+        let relative = path.strip_prefix(root).unwrap();
+        if relative != Path::new("") && ignore::is_ignored(relative, patterns) {
+            return Ok(());
+        }
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                visit(root, &entry?.path(), files, patterns, left_out)?;
+            }
+        } else if !files.contains(relative) {
+            let names: Vec<String> = relative.iter().map(|name| name.to_string_lossy().into_owned()).collect();
+            left_out.push(format!("/{}", names.join("/")));
+        }
+        Ok(())
+    }
+    let files: HashSet<&Path> = files.iter().map(PathBuf::as_path).collect();
+    let mut left_out = Vec::new();
+    visit(src, src, &files, patterns, &mut left_out)?;
+    left_out.sort();
+    Ok(left_out)
+}
@@ -0,0 +1,175 @@
+use cargo::{CargoResult, core::Shell};
+use state::Annotation;
+use std::{
+    collections::BTreeMap,
+    env,
+    fs,
+    path::Path
+};
+use toml;
+use urls;
+
+/// An entry in one of the root manifest's `[patch]` tables
+pub struct Patch {
+    /// The source being patched, like `crates-io`
+    pub registry: String,
+    pub spec: toml::Value
+}
+
+/// Read all `[patch]` entries of a manifest, keyed by crate name
+pub fn read(manifest: &Path) -> CargoResult<BTreeMap<String, Patch>> {
+    let parsed: toml::Value = toml::from_str(&fs::read_to_string(manifest)?)?;
+    let mut patches = BTreeMap::new();
+
+    if let Some(tables) = parsed.get("patch").and_then(|patch| patch.as_table()) {
+        for (registry, table) in tables {
+            if let Some(table) = table.as_table() {
+                for (name, spec) in table {
+                    patches.insert(name.clone(), Patch {
+                        registry: registry.clone(),
+                        spec: spec.clone()
+                    });
+                }
+            }
+        }
+    }
+    Ok(patches)
+}
+
+/// Describe where a patch points, like `git = "..."`
+pub fn describe(spec: &toml::Value) -> String {
+    match spec {
+        toml::Value::Table(table) => table.iter()
+            .filter(|(key, _)| ["git", "path", "branch", "tag", "rev", "version"].contains(&key.as_str()))
+            .map(|(key, value)| format!("{} = {}", key, value))
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string()
+    }
+}
+
+/// The keys a replacement file can use in an entry
+pub const KEYS: &[&str] = &["git", "branch", "tag", "rev", "path", "target", "profiles", "env", "owner", "reason", "upstream", "expires", "max-age"];
+
+/// Where an entry in a replacement file points, as it would be given to
+/// --replace. Relative paths are relative to `dir`, the file's directory,
+/// and environment variables like `${HOME}` are expanded in both URLs and
+/// paths. Returns why it can't be used if it can't.
+pub fn location(spec: &toml::Value, dir: &Path) -> Result<String, String> {
+    if let Some(url) = spec.get("git").and_then(|git| git.as_str()) {
+        let url = expand(url)?;
+        let reference = urls::REFERENCES.iter()
+            .filter_map(|key| spec.get(*key).and_then(|value| value.as_str()).map(|value| (key, value)))
+            .next();
+        return Ok(match reference {
+            Some((key, value)) => format!("{}?{}={}", url, key, value),
+            None => url
+        });
+    }
+    match spec.get("path").and_then(|path| path.as_str()) {
+        Some(path) => Ok(format!("path:{}", dir.join(expand(path)?).display())),
+        None => Err(format!("isn't a git or path dependency ({})", describe(spec)))
+    }
+}
+/// Replace every `${VAR}` with the value of that environment variable
+fn expand(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("has an unclosed ${{ in {:?}", value))
+        };
+        let var = &rest[start + 2..end];
+        match env::var(var) {
+            Ok(var) => {
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(&var);
+            },
+            Err(_) => return Err(format!("uses ${{{}}}, which isn't set", var))
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Read the notes on a replacement from an entry in a replacement file
+pub fn annotation(spec: &toml::Value) -> Annotation {
+    let get = |key: &str| spec.get(key).and_then(|value| value.as_str()).map(String::from);
+    Annotation {
+        owner: get("owner"),
+        reason: get("reason"),
+        upstream: get("upstream"),
+        expires: get("expires"),
+        max_age: spec.get("max-age").and_then(|days| days.as_integer()),
+        added: None
+    }
+}
+
+/// Check the conditions a replacement file can put on an entry: `profiles`,
+/// a list of profiles it's used for, and `env`, a variable that has to be set.
+/// Returns why the entry doesn't apply, if it doesn't.
+pub fn unmet_condition(spec: &toml::Value, profile: &str) -> Option<String> {
+    if let Some(profiles) = spec.get("profiles").and_then(|profiles| profiles.as_array()) {
+        let profiles: Vec<&str> = profiles.iter().filter_map(|profile| profile.as_str()).collect();
+        if !profiles.contains(&profile) {
+            return Some(format!("only used for the profiles {}", profiles.join(", ")));
+        }
+    }
+    if let Some(var) = spec.get("env").and_then(|var| var.as_str()) {
+        if env::var_os(var).map(|value| value.is_empty()).unwrap_or(true) {
+            return Some(format!("only used when {} is set", var));
+        }
+    }
+    None
+}
+
+/// Warn about the ways a manifest already overrides `name`, and explain
+/// which source ends up being used once it's replaced with `url`
+pub fn warn_overrides(shell: &mut Shell, manifest: &Path, is_root: bool, name: &str, url: &str) -> CargoResult<()> {
+    let parsed: toml::Value = toml::from_str(&fs::read_to_string(manifest)?)?;
+    let display = manifest.display();
+
+    // The root's [patch] entries are reported up front
+    if !is_root {
+        if let Some(tables) = parsed.get("patch").and_then(|patch| patch.as_table()) {
+            for (registry, table) in tables {
+                if let Some(spec) = table.get(name) {
+                    shell.warn(format!(
+                        "{} overrides {} in [patch.{}] ({}). Cargo only honors [patch] in the workspace root, so {} will be used",
+                        display, name, registry, describe(spec), url
+                    ))?;
+                }
+            }
+        }
+    }
+    if let Some(table) = parsed.get("replace").and_then(|replace| replace.as_table()) {
+        for (key, spec) in table {
+            if key == name || key.starts_with(&format!("{}:", name)) {
+                let used = if is_root {
+                    "That entry only matches the original source"
+                } else {
+                    "Cargo only honors [replace] in the workspace root"
+                };
+                shell.warn(format!(
+                    "{} overrides {} in [replace] ({}). {}, so {} will be used",
+                    display, key, describe(spec), used, url
+                ))?;
+            }
+        }
+    }
+    for table in &["dependencies", "dev-dependencies", "build-dependencies"] {
+        let path = parsed.get(table)
+            .and_then(|deps| deps.get(name))
+            .and_then(|dep| dep.get("path"))
+            .and_then(|path| path.as_str());
+        if let Some(path) = path {
+            shell.warn(format!(
+                "{} depends on {} through a path ({}). The path is rewritten to point to {} instead",
+                display, name, path, url
+            ))?;
+        }
+    }
+    Ok(())
+}
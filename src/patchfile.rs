@@ -0,0 +1,125 @@
+use cargo::CargoResult;
+use cli::Args;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf}
+};
+use toml;
+
+/// The names the file is looked for under, in the root of the workspace
+const NAMES: &[&str] = &["Patch.toml", "cargo-patch.toml"];
+
+/// The replacements and settings a workspace declares for everyone working on it,
+/// in a Patch.toml or the metadata of its root manifest
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchFile {
+    /// Entries like the ones in --replace-toml files, or just a URL
+    #[serde(default)]
+    pub replace: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    pub options: Options
+}
+/// Defaults for the flags of the same name
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Options {
+    pub mode: Option<String>,
+    pub profile: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub exclude: Option<Vec<String>>,
+    pub copy_ignore: Option<Vec<String>>,
+    pub relax_version: Option<String>,
+    pub force_version_compat: Option<bool>,
+    pub dedupe_versions: Option<bool>,
+    pub merge_patch: Option<bool>,
+    pub original_manifest: Option<bool>,
+    pub packaged_only: Option<bool>,
+    pub update_deny: Option<bool>,
+    pub update_lock: Option<bool>,
+    pub exclude_copies: Option<bool>,
+    pub allow_outside_paths: Option<bool>,
+    pub low_memory: Option<bool>,
+    pub verbose: Option<bool>
+}
+
+/// Find the file in the root of the workspace, if there is one
+pub fn find(root: &Path) -> Option<PathBuf> {
+    NAMES.iter().map(|name| root.join(name)).find(|path| path.exists())
+}
+
+impl PatchFile {
+    pub fn load(path: &Path) -> CargoResult<Self> {
+        let file: Self = toml::from_str(&fs::read_to_string(path)?)?;
+        Ok(file.expand())
+    }
+    /// Read the same settings from `[package.metadata.patch]` or
+    /// `[workspace.metadata.patch]` in a manifest, if it has them
+    pub fn from_manifest(manifest: &Path) -> CargoResult<Option<Self>> {
+        let parsed: toml::Value = toml::from_str(&fs::read_to_string(manifest)?)?;
+        let metadata = ["package", "workspace"].iter()
+            .filter_map(|table| parsed.get(table))
+            .filter_map(|table| table.get("metadata"))
+            .filter_map(|metadata| metadata.get("patch"))
+            .next();
+        match metadata {
+            Some(metadata) => {
+                let file: Self = metadata.clone().try_into()?;
+                Ok(Some(file.expand()))
+            },
+            None => Ok(None)
+        }
+    }
+    /// `name = "url"` is short for `name = { git = "url" }`
+    fn expand(mut self) -> Self {
+        for spec in self.replace.values_mut() {
+            if let toml::Value::String(url) = spec.clone() {
+                let mut table = toml::value::Table::new();
+                table.insert(String::from("git"), toml::Value::String(url));
+                *spec = toml::Value::Table(table);
+            }
+        }
+        self
+    }
+    /// Fill in the settings that weren't given as flags. Paths are relative
+    /// to `dir`, the directory of the file. Returns an error message if a
+    /// setting is invalid.
+    pub fn apply(&self, dir: &Path, args: &mut Args) -> Result<(), String> {
+        let options = &self.options;
+        if args.mode.is_none() {
+            if let Some(ref mode) = options.mode {
+                args.mode = Some(mode.parse()?);
+            }
+        }
+        if args.profile.is_none() {
+            args.profile = options.profile.clone();
+        }
+        if args.relax_version.is_none() {
+            if let Some(ref relax) = options.relax_version {
+                args.relax_version = Some(relax.parse()?);
+            }
+        }
+        if args.output_dir.is_none() {
+            args.output_dir = options.output_dir.as_ref().map(|output| dir.join(output));
+        }
+        args.exclude.extend(options.exclude.iter().flat_map(|exclude| exclude.iter().cloned()));
+        args.copy_ignore.extend(options.copy_ignore.iter().flat_map(|ignore| ignore.iter().cloned()));
+        // Flags can only turn these on
+        let set = |flag: &mut bool, option: Option<bool>| *flag = *flag || option.unwrap_or(false);
+        set(&mut args.force_version_compat, options.force_version_compat);
+        set(&mut args.dedupe_versions, options.dedupe_versions);
+        set(&mut args.merge_patch, options.merge_patch);
+        set(&mut args.original_manifest, options.original_manifest);
+        set(&mut args.packaged_only, options.packaged_only);
+        set(&mut args.update_deny, options.update_deny);
+        set(&mut args.update_lock, options.update_lock);
+        set(&mut args.exclude_copies, options.exclude_copies);
+        set(&mut args.allow_outside_paths, options.allow_outside_paths);
+        set(&mut args.low_memory, options.low_memory);
+        if options.verbose == Some(true) && args.verbose == 0 && !args.quiet {
+            args.verbose = 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use cargo::CargoResult;
+use selector::Selector;
+use state::Annotation;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path
+};
+use toml;
+
+/// Everything a run would do, written by `cargo patch plan` to be reviewed,
+/// and carried out as it is by `cargo patch apply`
+#[derive(Default, Serialize, Deserialize)]
+pub struct Plan {
+    pub replace: BTreeMap<String, String>,
+    /// Replacements restricted to a target, and its cfg
+    #[serde(default)]
+    pub targets: BTreeMap<String, String>,
+    /// Replacements restricted to some of the crates with their name
+    #[serde(default)]
+    pub selectors: BTreeMap<String, Selector>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, Annotation>,
+    /// What's done to every package depending on the replaced crates
+    #[serde(default, rename = "step")]
+    pub steps: Vec<Step>
+}
+/// The copy and manifest edit made for one package. Paths are relative to the workspace root.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    /// The package ID
+    pub package: String,
+    /// Where the package is copied to, if it isn't rewritten in place
+    pub copy: Option<String>,
+    /// The manifest that's rewritten
+    pub manifest: String,
+    /// Where its dependencies are pointed to, by name
+    pub dependencies: BTreeMap<String, String>
+}
+impl Plan {
+    pub fn load(path: &Path) -> CargoResult<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+    pub fn save(&self, path: &Path) -> CargoResult<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+    /// Describe how the steps of two plans differ, if they do
+    pub fn differences(&self, other: &[Step]) -> Vec<String> {
+        let mut differences = Vec::new();
+        for step in &self.steps {
+            match other.iter().find(|other| other.package == step.package) {
+                None => differences.push(format!("{} is no longer touched", step.package)),
+                Some(other) if other != step => differences.push(format!("{} would be changed differently", step.package)),
+                Some(_) => ()
+            }
+        }
+        for step in other {
+            if !self.steps.iter().any(|planned| planned.package == step.package) {
+                differences.push(format!("{} would be touched too", step.package));
+            }
+        }
+        differences
+    }
+}
@@ -0,0 +1,84 @@
+use cargo::{CargoResult, util::Config};
+use http;
+use serde_json;
+use std::fmt;
+
+/// A pull request on GitHub, or a merge request on GitLab
+pub struct PullRequest {
+    pub host: String,
+    /// The repository it's opened against, like `serde-rs/serde`
+    pub repository: String,
+    pub number: u64
+}
+impl fmt::Display for PullRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}#{}", self.host, self.repository, self.number)
+    }
+}
+
+/// Replacements from pull requests are written as `pr:<repository>#<number>`
+pub const PREFIX: &str = "pr:";
+
+/// Parse the part after `pr:`, like `serde-rs/serde#2450` or
+/// `gitlab.com/group/project#12`. The host defaults to github.com.
+pub fn parse(spec: &str) -> Result<PullRequest, String> {
+    let invalid = || format!("expected pr:owner/repo#number, not {:?}", spec);
+    let hash = spec.rfind('#').ok_or_else(invalid)?;
+    let number = spec[hash + 1..].parse().map_err(|_| invalid())?;
+    let path = spec[..hash].trim_matches('/');
+    let (host, repository) = match path.find('/') {
+        Some(slash) if path[..slash].contains('.') => (&path[..slash], &path[slash + 1..]),
+        _ => ("github.com", path)
+    };
+    if repository.split('/').count() < 2 || repository.split('/').any(str::is_empty) {
+        return Err(invalid());
+    }
+    Ok(PullRequest {
+        host: host.to_string(),
+        repository: repository.to_string(),
+        number
+    })
+}
+
+/// Ask the host where the pull request's changes are, and return them as a
+/// git URL of its head branch, like `https://github.com/me/serde?branch=fix`.
+/// Returns None if there's no such pull request.
+pub fn head(config: &Config, pull: &PullRequest) -> CargoResult<Option<String>> {
+    if pull.host == "github.com" {
+        let url = format!("https://api.github.com/repos/{}/pulls/{}", pull.repository, pull.number);
+        let json = match get(config, &url)? {
+            Some(json) => json,
+            None => return Ok(None)
+        };
+        let repository = json.pointer("/head/repo/clone_url").and_then(|url| url.as_str());
+        let branch = json.pointer("/head/ref").and_then(|branch| branch.as_str());
+        return Ok(match (repository, branch) {
+            (Some(repository), Some(branch)) => Some(format!("{}?branch={}", repository, branch)),
+            // The fork was deleted
+            _ => None
+        });
+    }
+    let api = format!("https://{}/api/v4/projects", pull.host);
+    let url = format!("{}/{}/merge_requests/{}", api, pull.repository.replace('/', "%2F"), pull.number);
+    let json = match get(config, &url)? {
+        Some(json) => json,
+        None => return Ok(None)
+    };
+    let (project, branch) = match (json["source_project_id"].as_u64(), json["source_branch"].as_str()) {
+        (Some(project), Some(branch)) => (project, branch.to_string()),
+        _ => return Ok(None)
+    };
+    let json = match get(config, &format!("{}/{}", api, project))? {
+        Some(json) => json,
+        None => return Ok(None)
+    };
+    Ok(json["http_url_to_repo"].as_str().map(|repository| format!("{}?branch={}", repository, branch)))
+}
+
+/// Fetch a JSON document, or None if the server says it's not there
+fn get(config: &Config, url: &str) -> CargoResult<Option<serde_json::Value>> {
+    match http::get(config, url)? {
+        Some(body) => Ok(Some(serde_json::from_slice(&body)?)),
+        None => Ok(None)
+    }
+}
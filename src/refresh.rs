@@ -0,0 +1,92 @@
+use cargo::{CargoResult, core::Shell};
+use errors::{self, Code};
+use hash::hash_files;
+use state::{Copy, State};
+use std::{fs, path::Path};
+use sync;
+
+/// How a copy compares to the source it was copied from
+pub enum Staleness {
+    Fresh,
+    /// The source changed since the copy was made
+    Stale,
+    /// The copy was edited since cargo-patch last touched it
+    Edited
+}
+
+/// Compare a copy to its source, ignoring the rewritten manifest and the
+/// files left out of it by the globs in `ignore`
+pub fn check(copy: &Copy, source: &Path, dest: &Path, ignore: &[String]) -> CargoResult<Staleness> {
+    let edits = sync::edits(source, dest, ignore)?;
+    if edits.changed.is_empty() && edits.removed.is_empty() {
+        return Ok(Staleness::Fresh);
+    }
+    let mut current = hash_files(dest, ignore)?;
+    let mut recorded = copy.files.clone();
+    current.remove("Cargo.toml");
+    recorded.remove("Cargo.toml");
+    Ok(if current == recorded { Staleness::Stale } else { Staleness::Edited })
+}
+
+/// Bring the files of a copy up to date with its source, keeping the rewritten manifest
+pub fn refresh(source: &Path, dest: &Path, ignore: &[String]) -> CargoResult<()> {
+    let edits = sync::edits(source, dest, ignore)?;
+    for file in edits.changed.iter().chain(&edits.removed) {
+        let original = source.join(file);
+        let target = dest.join(file);
+        if original.exists() {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&original, &target)?;
+        } else {
+            fs::remove_file(&target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Refresh the copies of `name`, or every copy, whose sources changed
+pub fn update(shell: &mut Shell, basedir: &Path, name: Option<&str>) -> CargoResult<()> {
+    let mut state = State::load(basedir)?;
+    let dirs = match name {
+        Some(name) => state.find(name),
+        None => state.copies.keys().cloned().collect()
+    };
+    if dirs.is_empty() {
+        match name {
+            Some(name) => return Err(errors::error(Code::CopyNotFound, format!("There is no copy of {} in {}", name, basedir.display()))),
+            None => shell.status("Fresh", "nothing was copied")?
+        }
+        return Ok(());
+    }
+
+    let mut updated = 0;
+    for dir in &dirs {
+        let dest = basedir.join(dir);
+        let (origin, source) = {
+            let copy = &state.copies[dir];
+            (copy.origin(), copy.source.clone())
+        };
+        let source = Path::new(&source);
+        if !source.exists() {
+            shell.warn(format!("skipping {}, {} is gone", dir, source.display()))?;
+            continue;
+        }
+        match check(&state.copies[dir], source, &dest, &state.ignored(dir))? {
+            Staleness::Fresh => continue,
+            Staleness::Edited => {
+                shell.warn(format!("skipping {}, it has local edits. Use `cargo patch sync` to merge them", dir))?;
+                continue;
+            },
+            Staleness::Stale => ()
+        }
+        shell.status("Updating", format!("{} from {}", dir, source.display()))?;
+        refresh(source, &dest, &state.ignored(dir))?;
+        state.record(dir, origin, source, &dest)?;
+        updated += 1;
+    }
+    state.save(basedir)?;
+    shell.status("Updated", format!("{} of {} copies", updated, dirs.len()))?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use cargo::{CargoResult, util::config::Config};
+use errors::{self, Code};
+use events;
+use interrupt::{self, Rollback};
+use manifest::{self, PackagePath};
+use state::State;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command
+};
+use versions;
+
+/// Run a command through the shell
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+/// For every replacement, point everything back to the newest release on
+/// crates.io, run `test`, and put everything back again. Replacements for
+/// which the test passes can likely be dropped.
+pub fn retry(config: &Config, basedir: &Path, members: &[PathBuf], lockfile: &Path, test: &str) -> CargoResult<()> {
+    let state = State::load(basedir)?;
+    let manifests: Vec<PathBuf> = members.iter()
+        .cloned()
+        .chain(state.copies.keys().map(|dir| basedir.join(dir).join("Cargo.toml")))
+        .collect();
+    let inherited = manifest::Inherited::read(&lockfile.with_file_name("Cargo.toml"))?;
+
+    interrupt::install()?;
+    let mut droppable = Vec::new();
+    for (name, url) in &state.replace {
+        let newest = match versions::newest_published(config, name)? {
+            Some(version) => version,
+            None => {
+                config.shell().status("Skipping", format!("{}, it isn't published on crates.io", name))?;
+                continue;
+            }
+        };
+        match versions::replacement(config, name, url) {
+            Ok(Some(ref replacement)) if newest < replacement.version => {
+                config.shell().status("Skipping", format!("{} {}, it's older than the replacement ({})", name, newest, replacement.version))?;
+                continue;
+            },
+            Ok(_) => (),
+            Err(err) => config.shell().warn(format!("failed to fetch {}: {}", url, err))?
+        }
+
+        config.shell().status("Trying", format!("{} {} instead of {}", name, newest, url))?;
+        let mut replaces = HashMap::new();
+        replaces.insert(name.clone(), PackagePath::Registry(newest.clone()));
+        let mut rollback = Rollback::default();
+        if lockfile.exists() {
+            rollback.manifest(lockfile.to_path_buf(), fs::read_to_string(lockfile)?);
+        }
+        for path in &manifests {
+            let contents = fs::read_to_string(path)?;
+            if !manifest::depends_on(&contents, name)? {
+                continue;
+            }
+            let rewritten = match manifest::rewrite(&contents, "", manifest::KINDS, &replaces, &HashMap::new(), &HashMap::new(), &inherited) {
+                Ok(rewritten) => rewritten,
+                Err(err) => {
+                    rollback.run();
+                    return Err(err);
+                }
+            };
+            rollback.manifest(path.clone(), contents);
+            fs::write(path, rewritten)?;
+        }
+
+        let status = shell(test).status();
+        rollback.run();
+        if interrupt::check().is_err() {
+            return Err(errors::error(Code::Interrupted, format!("Put the replacement of {} back", name)));
+        }
+        if status?.success() {
+            config.shell().status("Passed", format!("{} with {} {}", test, name, newest))?;
+            droppable.push((name, newest));
+        } else {
+            config.shell().status("Failed", format!("{} with {} {}, the replacement is still needed", test, name, newest))?;
+        }
+    }
+
+    if droppable.is_empty() {
+        events::say("All replacements are still needed");
+    } else {
+        events::say("These replacements can likely be dropped:");
+        for (name, version) in &droppable {
+            events::say(format!("  {} (use {} from crates.io)", name, version));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf}
+};
+
+/// Resolve `.` and `..` without touching the file system. A `..` past the
+/// root stays at the root, while one past the start of a relative path is kept.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => { normalized.pop(); },
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => (),
+                _ => normalized.push("..")
+            },
+            component => normalized.push(component.as_os_str())
+        }
+    }
+    normalized
+}
+
+/// Resolve symlinks in the part of `path` that exists, so a link can't
+/// be used to get out of a directory
+fn resolve(path: &Path) -> PathBuf {
+    let path = normalize(path);
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(resolved) = fs::canonicalize(existing) {
+            return rest.iter().rev().fold(resolved, |path, component| path.join(component));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            },
+            _ => return path
+        }
+    }
+}
+
+/// The directories cargo-patch is allowed to write to
+pub struct Sandbox {
+    roots: Vec<PathBuf>
+}
+impl Sandbox {
+    pub fn new(roots: &[&Path]) -> Self {
+        Self {
+            roots: roots.iter().map(|root| resolve(root)).collect()
+        }
+    }
+    /// Returns true if writing to `path` stays inside one of the directories,
+    /// after resolving `..` and following symlinks
+    pub fn contains(&self, path: &Path) -> bool {
+        let resolved = resolve(path);
+        self.roots.iter().any(|root| resolved.starts_with(root))
+    }
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+}
@@ -0,0 +1,118 @@
+use cargo::{
+    CargoResult,
+    core::{GitReference, SourceId}
+};
+use errors::{self, Code};
+use state::State;
+use std::{
+    fs,
+    path::{Path, PathBuf}
+};
+use sync;
+use urls;
+
+/// Quote a string for the shell
+fn quote(string: &str) -> String {
+    format!("'{}'", string.replace('\'', "'\\''"))
+}
+
+/// Write a file with a here-doc. Absolute paths to the copies are replaced
+/// with `$COPIES`, so the script works wherever it's run.
+fn heredoc(script: &mut String, path: &str, contents: &str, basedir: &Path) {
+    // The delimiter isn't quoted, so that $COPIES is expanded, which means
+    // everything else the shell would expand has to be escaped
+    let escaped = contents
+        .replace('\\', "\\\\")
+        .replace('$', "\\$")
+        .replace('`', "\\`")
+        .replace(&*basedir.to_string_lossy(), "${COPIES}");
+    script.push_str(&format!("cat > {} <<CARGO_PATCH_EOF\n", quote(path)));
+    script.push_str(&escaped);
+    if !escaped.ends_with('\n') {
+        script.push('\n');
+    }
+    script.push_str("CARGO_PATCH_EOF\n");
+}
+
+/// Create a shell script that recreates the copies and rewritten manifests
+/// without cargo-patch, to be run in the root of the workspace
+pub fn export(root: &Path, basedir: &Path, members: &[PathBuf]) -> CargoResult<String> {
+    let state = State::load(basedir)?;
+    let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_string_lossy().into_owned();
+    let mut script = String::new();
+
+    script.push_str("#!/bin/sh\n");
+    script.push_str("# Recreates what cargo-patch did, run this in the root of the workspace\n");
+    script.push_str("set -e\n");
+    script.push_str(&format!("COPIES=\"$(pwd)/{}\"\n", relative(basedir)));
+    script.push_str("mkdir -p \"$COPIES\"\n");
+
+    for (dir, copy) in &state.copies {
+        let dest = format!("\"$COPIES\"/{}", quote(dir));
+        script.push_str(&format!("\n# {}\n", copy.id));
+        if copy.source_id.is_empty() {
+            return Err(errors::error(Code::UnknownSource, format!(
+                "Can't tell where {} came from, it was copied by an older cargo-patch. Copy it again first", dir
+            )));
+        }
+        let source_id = SourceId::from_url(&copy.source_id)?;
+        if source_id.is_default_registry() {
+            script.push_str(&format!("mkdir {}\n", dest));
+            script.push_str(&format!(
+                "curl -sSfL {} | tar xzf - -C {} --strip-components=1\n",
+                quote(&format!("https://crates.io/api/v1/crates/{}/{}/download", copy.name, copy.version)),
+                dest
+            ));
+        } else if source_id.is_git() {
+            // The exact commit is known if it came from Cargo.lock
+            let rev = match (source_id.precise(), source_id.git_reference()) {
+                (Some(precise), _) => precise,
+                (None, Some(&GitReference::Branch(ref rev)))
+                    | (None, Some(&GitReference::Tag(ref rev)))
+                    | (None, Some(&GitReference::Rev(ref rev))) => rev.as_str(),
+                (None, None) => "HEAD"
+            };
+            script.push_str(&format!("git clone --quiet {} {}\n", quote(&source_id.url().to_string()), dest));
+            script.push_str(&format!("git -C {} checkout --quiet {}\n", dest, quote(rev)));
+        } else if source_id.is_path() {
+            script.push_str("# This came from a local path, which only exists on the original machine\n");
+            script.push_str(&format!("cp -R {} {}\n", quote(&copy.source), dest));
+        } else {
+            return Err(errors::error(Code::UnexportableSource, format!("{} comes from {}", dir, copy.source_id)));
+        }
+
+        let original = Path::new(&copy.source);
+        let copied = basedir.join(dir);
+        let edits = sync::edits(original, &copied, &state.ignored(dir))?;
+        for file in edits.changed.iter().map(String::as_str).chain(Some("Cargo.toml")) {
+            let path = format!("{}/{}/{}", relative(basedir), dir, file);
+            match fs::read_to_string(copied.join(file)) {
+                Ok(contents) => {
+                    match Path::new(file).parent() {
+                        Some(parent) if parent != Path::new("") => script.push_str(&format!("mkdir -p {}/{}\n", dest, quote(&parent.to_string_lossy()))),
+                        _ => ()
+                    }
+                    heredoc(&mut script, &path, &contents, basedir);
+                },
+                Err(_) => script.push_str(&format!("# {} isn't text, copy it by hand\n", path))
+            }
+        }
+        for file in &edits.removed {
+            script.push_str(&format!("rm -f {}/{}\n", dest, quote(file)));
+        }
+    }
+
+    let mentions = |contents: &str| contents.contains(&*basedir.to_string_lossy())
+        || state.replace.values().any(|url| match urls::local_path(url) {
+            Some(path) => contents.contains(&*path.to_string_lossy()),
+            None => contents.contains(url.as_str())
+        });
+    for manifest in members {
+        let contents = fs::read_to_string(manifest)?;
+        if mentions(&contents) {
+            script.push('\n');
+            heredoc(&mut script, &relative(manifest), &contents, basedir);
+        }
+    }
+    Ok(script)
+}
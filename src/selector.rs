@@ -0,0 +1,184 @@
+use graph::{Node, SourceKind};
+use std::fmt;
+use urls;
+
+/// Narrows a replacement down to some of the crates with its name
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Selector {
+    /// A version, or the start of one like `1.2`
+    pub version: Option<String>,
+    /// The registry the crate comes from, by URL or `crates-io`
+    pub registry: Option<String>,
+    /// The git repository the crate comes from
+    pub git: Option<String>
+}
+impl Selector {
+    pub fn is_empty(&self) -> bool {
+        self.version.is_none() && self.registry.is_none() && self.git.is_none()
+    }
+    pub fn matches(&self, node: &Node) -> bool {
+        if let Some(ref version) = self.version {
+            let actual = node.version.to_string();
+            let mut actual = actual.split('.');
+            if !version.split('.').all(|part| actual.next() == Some(part)) {
+                return false;
+            }
+        }
+        if let Some(ref registry) = self.registry {
+            if node.source.kind != SourceKind::Registry {
+                return false;
+            }
+            let same = if registry == "crates-io" {
+                node.source.default_registry
+            } else {
+                urls::canonicalize(registry) == urls::canonicalize(&node.source.url)
+            };
+            if !same {
+                return false;
+            }
+        }
+        if let Some(ref git) = self.git {
+            if node.source.kind != SourceKind::Git || urls::canonicalize(git) != urls::canonicalize(&node.source.url) {
+                return false;
+            }
+        }
+        true
+    }
+}
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref version) = self.version {
+            write!(f, "@{}", version)?;
+        }
+        if let Some(ref registry) = self.registry {
+            write!(f, "(registry={})", registry)?;
+        }
+        if let Some(ref git) = self.git {
+            write!(f, "(git={})", git)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a crate name like `foo@1.2` or `foo(registry=url)` into the name and what it selects
+pub fn parse(spec: &str) -> Result<(String, Selector), String> {
+    let mut selector = Selector::default();
+    let mut rest = spec.to_string();
+    if let Some(open) = spec.find('(') {
+        let close = match spec.rfind(')') {
+            Some(close) if close > open => close,
+            _ => return Err(format!("unclosed parenthesis in {:?}", spec))
+        };
+        let source = &spec[open + 1..close];
+        let (key, value) = match source.find('=') {
+            Some(equals) => (source[..equals].trim(), source[equals + 1..].trim()),
+            None => ("", "")
+        };
+        match key {
+            _ if value.is_empty() => return Err(format!("expected registry=url or git=url in the parentheses of {:?}", spec)),
+            "registry" => selector.registry = Some(value.to_string()),
+            "git" => selector.git = Some(value.to_string()),
+            _ => return Err(format!("unknown source {:?} in {:?}, expected registry or git", key, spec))
+        }
+        rest = format!("{}{}", &spec[..open], &spec[close + 1..]);
+    }
+    let spec = &*rest;
+    let name = match spec.find('@') {
+        Some(at) => {
+            let version = &spec[at + 1..];
+            if version.is_empty() || !version.split('.').all(|part| !part.is_empty()) {
+                return Err(format!("expected a version like 1.2 after the @ in {:?}", spec));
+            }
+            selector.version = Some(version.to_string());
+            &spec[..at]
+        },
+        None => spec
+    };
+    if name.is_empty() {
+        return Err(format!("no crate name in {:?}", spec));
+    }
+    Ok((name.to_string(), selector))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::Source;
+    use semver::Version;
+    use std::path::PathBuf;
+
+    fn node(version: &str, kind: SourceKind, url: &str) -> Node {
+        Node {
+            id: format!("foo {} ({})", version, url),
+            name: String::from("foo"),
+            version: Version::parse(version).unwrap(),
+            source: Source {
+                kind,
+                url: url.to_string(),
+                id: url.to_string(),
+                default_registry: url == "https://github.com/rust-lang/crates.io-index"
+            },
+            manifest_path: PathBuf::from("/registry/foo/Cargo.toml"),
+            dependencies: Vec::new(),
+            deps: Vec::new()
+        }
+    }
+
+    #[test]
+    fn parse_name() {
+        assert_eq!(parse("foo"), Ok((String::from("foo"), Selector::default())));
+    }
+
+    #[test]
+    fn parse_version() {
+        let (name, selector) = parse("foo@1.2").unwrap();
+        assert_eq!(name, "foo");
+        assert_eq!(selector.version, Some(String::from("1.2")));
+        assert_eq!(selector.to_string(), "@1.2");
+    }
+
+    #[test]
+    fn parse_source() {
+        let (name, selector) = parse("foo@1(registry=https://my-registry/index)").unwrap();
+        assert_eq!(name, "foo");
+        assert_eq!(selector.version, Some(String::from("1")));
+        assert_eq!(selector.registry, Some(String::from("https://my-registry/index")));
+
+        let (name, selector) = parse("foo(git = https://github.com/org/foo)").unwrap();
+        assert_eq!(name, "foo");
+        assert_eq!(selector.git, Some(String::from("https://github.com/org/foo")));
+        assert_eq!(selector.to_string(), "(git=https://github.com/org/foo)");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse("").is_err());
+        assert!(parse("@1.2").is_err());
+        assert!(parse("foo@").is_err());
+        assert!(parse("foo@1..2").is_err());
+        assert!(parse("foo(registry=url").is_err());
+        assert!(parse("foo(registry=)").is_err());
+        assert!(parse("foo(path=/foo)").is_err());
+    }
+
+    #[test]
+    fn matches_version() {
+        let (_, selector) = parse("foo@1.2").unwrap();
+        let registry = "https://github.com/rust-lang/crates.io-index";
+        assert!(selector.matches(&node("1.2.3", SourceKind::Registry, registry)));
+        assert!(!selector.matches(&node("1.20.0", SourceKind::Registry, registry)));
+        assert!(!selector.matches(&node("2.0.0", SourceKind::Registry, registry)));
+    }
+
+    #[test]
+    fn matches_source() {
+        let (_, selector) = parse("foo(registry=crates-io)").unwrap();
+        assert!(selector.matches(&node("1.0.0", SourceKind::Registry, "https://github.com/rust-lang/crates.io-index")));
+        assert!(!selector.matches(&node("1.0.0", SourceKind::Registry, "https://my-registry/index")));
+
+        let (_, selector) = parse("foo(git=git@github.com:org/foo.git)").unwrap();
+        assert!(selector.matches(&node("1.0.0", SourceKind::Git, "https://github.com/org/foo")));
+        assert!(!selector.matches(&node("1.0.0", SourceKind::Git, "https://github.com/other/foo")));
+        assert!(!selector.matches(&node("1.0.0", SourceKind::Registry, "https://github.com/org/foo")));
+    }
+}
@@ -0,0 +1,161 @@
+use cargo::{CargoResult, core::Shell};
+use errors::{self, Code};
+use serde_json;
+use state::State;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command
+};
+use sync;
+use toml;
+
+/// Where upstream repositories are checked out, inside the base directory
+pub const DIR: &str = ".send";
+
+/// Run git in `dir`, returning true if it succeeded
+fn git(dir: &Path, args: &[&str]) -> CargoResult<bool> {
+    Ok(Command::new("git").current_dir(dir).args(args).status()?.success())
+}
+
+/// The owner of a GitHub repository, like `foo` in `git@github.com:foo/bar`
+fn github_owner(url: &str) -> Option<&str> {
+    let rest = &url[url.find("github.com")? + "github.com".len()..];
+    rest.trim_left_matches(|c| c == '/' || c == ':').split('/').next()
+}
+
+/// Find the directory of the package called `name` in a checkout
+fn find_package(dir: &Path, name: &str) -> CargoResult<Option<PathBuf>> {
+    let manifest = dir.join("Cargo.toml");
+    if manifest.exists() {
+        let parsed: toml::Value = toml::from_str(&fs::read_to_string(&manifest)?)?;
+        if parsed.get("package").and_then(|package| package.get("name")).and_then(|name| name.as_str()) == Some(name) {
+            return Ok(Some(dir.to_path_buf()));
+        }
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() && !path.ends_with(".git") && !path.ends_with("target") {
+            if let Some(found) = find_package(&path, name)? {
+                return Ok(Some(found));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Turn the local edits of a copy into a commit on top of the crate's
+/// upstream repository. The commit is either written out with
+/// `git format-patch`, or pushed to `push` and opened as a pull request.
+pub fn send(shell: &mut Shell, basedir: &Path, name: &str, push: Option<&str>) -> CargoResult<()> {
+    let state = State::load(basedir)?;
+    let matching = state.find(name);
+    if matching.len() != 1 {
+        let details = if matching.is_empty() {
+            format!("There is no copy of {} in {}", name, basedir.display())
+        } else {
+            format!("There are several copies of {}, pick one of: {}", name, matching.join(", "))
+        };
+        return Err(errors::error(Code::CopyNotFound, details));
+    }
+    let dir = &matching[0];
+    let copy = &state.copies[dir];
+    let original = PathBuf::from(&copy.source);
+    let name = if copy.name.is_empty() { name } else { copy.name.as_str() };
+
+    let edits = sync::edits(&original, &basedir.join(dir), &state.ignored(dir))?;
+    if edits.changed.is_empty() && edits.removed.is_empty() {
+        shell.status("Fresh", format!("{} has no local edits", dir))?;
+        return Ok(());
+    }
+
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(original.join("Cargo.toml"))?)?;
+    let repository = match manifest.get("package").and_then(|package| package.get("repository")).and_then(|url| url.as_str()) {
+        Some(url) => url.to_string(),
+        None => return Err(errors::error(Code::UnknownUpstream, format!("{} doesn't say where its repository is", dir)))
+    };
+
+    // Packaged crates record which commit they were made from
+    let commit = match fs::read_to_string(original.join(".cargo_vcs_info.json")) {
+        Ok(info) => serde_json::from_str::<serde_json::Value>(&info)?
+            .pointer("/git/sha1")
+            .and_then(|sha| sha.as_str())
+            .map(String::from),
+        Err(_) => None
+    };
+
+    let checkout = basedir.join(DIR).join(dir);
+    if checkout.exists() {
+        fs::remove_dir_all(&checkout)?;
+    }
+    fs::create_dir_all(&checkout)?;
+    shell.status("Cloning", &repository)?;
+    if !git(&checkout, &["clone", "--quiet", &repository, "."])? {
+        return Ok(());
+    }
+    match commit {
+        Some(ref commit) => {
+            if !git(&checkout, &["checkout", "--quiet", commit])? {
+                return Ok(());
+            }
+        },
+        None => shell.warn(format!("don't know which commit {} was published from, using the default branch", dir))?
+    }
+
+    let package = match find_package(&checkout, name)? {
+        Some(package) => package,
+        None => return Err(errors::error(Code::UnknownUpstream, format!("{} doesn't contain a package called {}", repository, name)))
+    };
+    for file in &edits.changed {
+        let target = package.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(basedir.join(dir).join(file), &target)?;
+    }
+    for file in &edits.removed {
+        let target = package.join(file);
+        if target.exists() {
+            fs::remove_file(&target)?;
+        }
+    }
+
+    let branch = format!("cargo-patch/{}", name);
+    let message = format!("Local changes to {}", name);
+    if !git(&checkout, &["checkout", "--quiet", "-b", &branch])?
+            || !git(&checkout, &["add", "--all"])?
+            || !git(&checkout, &["commit", "--quiet", "-m", &message])? {
+        return Ok(());
+    }
+
+    match push {
+        None => {
+            let out = basedir.join(format!("{}.patches", dir));
+            if !git(&checkout, &["format-patch", "--quiet", "-1", "-o", &out.to_string_lossy()])? {
+                return Ok(());
+            }
+            shell.status("Wrote", format!("patches for {} to {}", repository, out.display()))?;
+        },
+        Some(remote) => {
+            let refspec = format!("HEAD:refs/heads/{}", branch);
+            if !git(&checkout, &["push", remote, &refspec])? {
+                return Ok(());
+            }
+            shell.status("Pushed", format!("{} to {}", branch, remote))?;
+            // gh wants owner:branch when the branch is in a fork
+            let head = match github_owner(remote) {
+                Some(owner) => format!("{}:{}", owner, branch),
+                None => branch.clone()
+            };
+            let created = Command::new("gh")
+                .current_dir(&checkout)
+                .args(&["pr", "create", "--repo", &repository, "--head", &head, "--title", &message, "--body", ""])
+                .status();
+            match created {
+                Ok(ref status) if status.success() => (),
+                _ => shell.warn(format!("couldn't open a pull request with gh, open one for {} against {} yourself", branch, repository))?
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use cargo::{CargoResult, core::Shell};
+use copy;
+use sandbox::normalize;
+use std::{
+    fs,
+    path::{Path, PathBuf}
+};
+use toml;
+
+#[cfg(unix)]
+fn link(_shell: &mut Shell, src: &Path, dst: &Path) -> CargoResult<()> {
+    Ok(::std::os::unix::fs::symlink(src, dst)?)
+}
+/// Symlinks need administrator rights or developer mode on Windows. Without
+/// them, directories are linked with junctions and files with hard links,
+/// and if even that fails, they're copied.
+#[cfg(windows)]
+fn link(shell: &mut Shell, src: &Path, dst: &Path) -> CargoResult<()> {
+    use std::{
+        os::windows::fs::{symlink_dir, symlink_file},
+        process::{Command, Stdio},
+        sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT}
+    };
+    // ERROR_PRIVILEGE_NOT_HELD
+    const NO_PRIVILEGE: i32 = 1314;
+    static WARNED: AtomicBool = ATOMIC_BOOL_INIT;
+
+    let linked = if src.is_dir() { symlink_dir(src, dst) } else { symlink_file(src, dst) };
+    match linked {
+        Err(ref err) if err.raw_os_error() == Some(NO_PRIVILEGE) => (),
+        other => return Ok(other?)
+    }
+    if !WARNED.swap(true, Ordering::SeqCst) {
+        shell.warn("creating symlinks isn't allowed, enable developer mode or run as administrator\n\
+                    Using junctions and hard links instead")?;
+    }
+    if src.is_dir() {
+        let junction = Command::new("cmd")
+            .arg("/C").arg("mklink").arg("/J").arg(dst).arg(src)
+            .stdout(Stdio::null())
+            .status()?;
+        if junction.success() {
+            return Ok(());
+        }
+    } else if fs::hard_link(src, dst).is_ok() {
+        return Ok(());
+    }
+    shell.warn(format!("couldn't link {}, copying it instead", src.display()))?;
+    Ok(copy::all(src, dst)?)
+}
+
+/// Where a file of the real workspace ends up in the shadow workspace
+pub fn locate(root: &Path, shadow: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix(root) {
+        Ok(relative) => shadow.join(relative),
+        Err(_) => path.to_path_buf()
+    }
+}
+
+/// Mirror the workspace at `root` into `shadow`. Directories containing
+/// members are recreated and their manifests copied, so they can be
+/// rewritten. Everything else is linked. Copies from previous runs, in
+/// `shadow/cargo-patch`, are kept.
+pub fn create(shell: &mut Shell, root: &Path, members: &[PathBuf], shadow: &Path) -> CargoResult<()> {
+    if shadow.exists() {
+        for entry in fs::read_dir(shadow)? {
+            let path = entry?.path();
+            if path.file_name().map(|name| name == "cargo-patch").unwrap_or(false) {
+                continue;
+            }
+            let is_link = fs::symlink_metadata(&path)?.file_type().is_symlink();
+            if path.is_dir() && !is_link {
+                fs::remove_dir_all(&path)?;
+            } else if path.is_dir() && cfg!(windows) {
+                // Directory symlinks and junctions
+                fs::remove_dir(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+    } else {
+        fs::create_dir_all(shadow)?;
+    }
+    mirror(shell, root, root, members, shadow)
+}
+fn mirror(shell: &mut Shell, root: &Path, dir: &Path, members: &[PathBuf], shadow: &Path) -> CargoResult<()> {
+    let dest = locate(root, shadow, dir);
+    if !dest.exists() {
+        fs::create_dir(&dest)?;
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let target = dest.join(path.file_name().expect("directory entry without a file name"));
+
+        if shadow.starts_with(&path) {
+            // This is where the shadow workspace lives, don't recurse into it
+            continue;
+        } else if path.is_dir() && members.iter().any(|member| member.starts_with(&path)) {
+            mirror(shell, root, &path, members, shadow)?;
+        } else if path.ends_with("Cargo.toml") {
+            let mut parsed: toml::Value = toml::from_str(&fs::read_to_string(&path)?)?;
+            absolutize(&mut parsed, dir, root);
+            fs::write(&target, toml::to_string_pretty(&parsed)?)?;
+        } else if path.ends_with("Cargo.lock") {
+            fs::copy(&path, &target)?;
+        } else {
+            link(shell, &path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Make `path` dependencies that point out of the workspace absolute,
+/// since the shadow workspace lives somewhere else
+fn absolutize(manifest: &mut toml::Value, dir: &Path, root: &Path) {
+    fn fix_table(deps: &mut toml::Value, dir: &Path, root: &Path) {
+        let deps = match deps.as_table_mut() {
+            Some(deps) => deps,
+            None => return
+        };
+        for dep in deps.values_mut() {
+            if let Some(dep) = dep.as_table_mut() {
+                let absolute = match dep.get("path").and_then(|path| path.as_str()) {
+                    Some(path) => normalize(&dir.join(path)),
+                    None => continue
+                };
+                if !absolute.starts_with(root) {
+                    dep.insert(String::from("path"), toml::Value::String(absolute.to_string_lossy().into_owned()));
+                }
+            }
+        }
+    }
+    fn fix_tables(tables: &mut toml::Value, dir: &Path, root: &Path) {
+        for table in &["dependencies", "dev-dependencies", "build-dependencies"] {
+            if let Some(deps) = tables.get_mut(table) {
+                fix_table(deps, dir, root);
+            }
+        }
+    }
+
+    fix_tables(manifest, dir, root);
+    if let Some(targets) = manifest.get_mut("target").and_then(|target| target.as_table_mut()) {
+        for target in targets.values_mut() {
+            fix_tables(target, dir, root);
+        }
+    }
+    if let Some(patches) = manifest.get_mut("patch").and_then(|patch| patch.as_table_mut()) {
+        for patch in patches.values_mut() {
+            fix_table(patch, dir, root);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use cargo::{
+    CargoResult,
+    core::{Shell, shell::Verbosity}
+};
+use events::{self, Event};
+use graph::Node;
+use semver::Version;
+use std::{fmt, path::PathBuf};
+
+/// Why a crate was left alone
+pub enum Reason {
+    /// The copy already exists, from a previous run or made by hand
+    AlreadyCopied(PathBuf),
+    /// An interrupted run already rewrote the manifest
+    AlreadyRewritten(PathBuf),
+    /// The crate already comes from the replacement
+    AlreadyReplaced(String),
+    /// Another version of the crate is replaced, and this one isn't compatible
+    IncompatibleVersion(Version),
+    /// The crate was excluded with --exclude
+    Excluded,
+    /// The replacement is only for other versions or sources, like the ones of foo@1.2
+    NotSelected(String),
+    /// The optional dependency on this crate isn't enabled by the features
+    FeatureDisabled(String),
+    /// The replacement of this dependency is only for another target, like cfg(windows)
+    WrongTarget(String, String)
+}
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::AlreadyCopied(path) => write!(f, "already copied to {}", path.display()),
+            Reason::AlreadyRewritten(path) => write!(f, "{} was already rewritten before the run was interrupted", path.display()),
+            Reason::AlreadyReplaced(url) => write!(f, "already points to {}", url),
+            Reason::IncompatibleVersion(version) => write!(f, "not compatible with the replacement's version {}", version),
+            Reason::Excluded => write!(f, "excluded"),
+            Reason::NotSelected(spec) => write!(f, "only {} is replaced", spec),
+            Reason::FeatureDisabled(name) => write!(f, "the optional dependency on {} isn't enabled by the features", name),
+            Reason::WrongTarget(name, cfg) => write!(f, "{} is only replaced for {}, and isn't a dependency for that target", name, cfg)
+        }
+    }
+}
+
+/// Tell the user a crate was skipped, including why if the shell is verbose.
+/// The reason is always in the event.
+pub fn print(shell: &mut Shell, package: &Node, reason: &Reason) -> CargoResult<()> {
+    events::emit(&Event::Skipped {
+        package_id: &package.id,
+        reason: reason.to_string()
+    });
+    if shell.verbosity() == Verbosity::Verbose {
+        shell.status("Skipping", format!("{} {}: {}", package.name, package.version, reason))
+    } else {
+        shell.status("Skipping", format!("{} {}", package.name, package.version))
+    }
+}
@@ -0,0 +1,207 @@
+use cargo::{CargoResult, core::PackageId};
+use dates;
+use graph::Node;
+use hash::{hash_file, hash_files};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path
+};
+use toml;
+
+pub const FILE: &str = ".state.toml";
+
+/// What cargo-patch has done to the tree, kept across runs
+#[derive(Default, Serialize, Deserialize)]
+pub struct State {
+    /// The replacements that have been applied, by crate name
+    #[serde(default)]
+    pub replace: BTreeMap<String, String>,
+    /// Every copy, by directory name
+    #[serde(default)]
+    pub copies: BTreeMap<String, Copy>,
+    /// Notes on why the replacements were made, by crate name
+    #[serde(default)]
+    pub annotations: BTreeMap<String, Annotation>,
+    /// Manifests rewritten outside of the copies, as they were before the first rewrite
+    #[serde(default)]
+    pub originals: BTreeMap<String, String>,
+    /// The hashes of those manifests, as they were last rewritten
+    #[serde(default)]
+    pub rewritten: BTreeMap<String, String>,
+    /// Files that didn't exist before cargo-patch wrote them
+    #[serde(default)]
+    pub created: BTreeSet<String>,
+    /// The globs of files left out of the copies, besides the built-in ones
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// The files cargo wouldn't package, left out of each copy made with
+    /// --packaged-only, by directory name
+    #[serde(default, rename = "left-out")]
+    pub left_out: BTreeMap<String, Vec<String>>
+}
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Annotation {
+    /// Who added the replacement
+    pub owner: Option<String>,
+    /// Why it's needed
+    pub reason: Option<String>,
+    /// Link to the upstream issue or pull request
+    pub upstream: Option<String>,
+    /// The date after which the replacement should be reviewed, like 2018-12-31
+    pub expires: Option<String>,
+    /// How many days the replacement should be kept before being reviewed
+    #[serde(rename = "max-age")]
+    pub max_age: Option<i64>,
+    /// The date the replacement was first made
+    pub added: Option<String>
+}
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        self.owner.is_none() && self.reason.is_none() && self.upstream.is_none()
+            && self.expires.is_none() && self.max_age.is_none()
+    }
+    /// Explain why the replacement is overdue for a review, if it is
+    pub fn expired(&self, today: i64) -> Option<String> {
+        if let Some(expires) = self.expires.as_ref().and_then(|date| dates::parse(date)) {
+            if today > expires {
+                return Some(format!("expired on {}", dates::format(expires)));
+            }
+        }
+        if let (Some(max_age), Some(added)) = (self.max_age, self.added.as_ref().and_then(|date| dates::parse(date))) {
+            if today - added > max_age {
+                return Some(format!("older than {} days, added on {}", max_age, dates::format(added)));
+            }
+        }
+        None
+    }
+}
+/// The package a copy was made of
+pub struct Origin {
+    /// The package ID, formatted like cargo does
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    /// Where it came from, as a URL `SourceId::from_url` understands
+    pub source_id: String
+}
+impl Origin {
+    pub fn new(id: &PackageId) -> Self {
+        Self {
+            id: id.to_string(),
+            name: id.name().to_string(),
+            version: id.version().to_string(),
+            source_id: id.source_id().to_url().to_string()
+        }
+    }
+    pub fn of(node: &Node) -> Self {
+        Self {
+            id: node.id.clone(),
+            name: node.name.clone(),
+            version: node.version.to_string(),
+            source_id: node.source.id.clone()
+        }
+    }
+}
+#[derive(Serialize, Deserialize)]
+pub struct Copy {
+    /// The package ID of the original, formatted like cargo does
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    /// Where the original came from, as a URL `SourceId::from_url` understands.
+    /// Empty for copies made before it was recorded.
+    #[serde(default, rename = "source-id")]
+    pub source_id: String,
+    /// Where the copy originally came from
+    pub source: String,
+    /// The hashes of every file, as they were when cargo-patch last touched them
+    pub files: BTreeMap<String, String>
+}
+impl Copy {
+    pub fn origin(&self) -> Origin {
+        Origin {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            source_id: self.source_id.clone()
+        }
+    }
+}
+impl State {
+    pub fn load(basedir: &Path) -> CargoResult<Self> {
+        let path = basedir.join(FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(&path)?)?)
+    }
+    pub fn save(&self, basedir: &Path) -> CargoResult<()> {
+        fs::write(basedir.join(FILE), toml::to_string(self)?)?;
+        Ok(())
+    }
+    /// Record the notes on a replacement, keeping the date it was first made
+    pub fn annotate(&mut self, name: &str, mut annotation: Annotation) {
+        if let Some(added) = self.annotations.get(name).and_then(|old| old.added.clone()) {
+            annotation.added = Some(added);
+        }
+        self.annotations.insert(name.to_string(), annotation);
+    }
+    /// Remember what a manifest looked like, unless it was rewritten or created before
+    pub fn remember(&mut self, manifest: &Path, original: &str) {
+        let manifest = manifest.to_string_lossy().into_owned();
+        if !self.created.contains(&manifest) {
+            self.originals.entry(manifest).or_insert_with(|| original.to_string());
+        }
+    }
+    /// Remember that a file didn't exist, unless it was written before
+    pub fn remember_missing(&mut self, path: &Path) {
+        let path = path.to_string_lossy().into_owned();
+        if !self.originals.contains_key(&path) {
+            self.created.insert(path);
+        }
+    }
+    /// Whether a file was edited since it was last rewritten
+    pub fn edited(&self, path: &str) -> CargoResult<bool> {
+        let file = Path::new(path);
+        match self.rewritten.get(path) {
+            Some(hash) if file.exists() => Ok(hash_file(file)? != *hash),
+            _ => Ok(false)
+        }
+    }
+    /// Hash a manifest outside of the copies that was just rewritten
+    pub fn rewrote(&mut self, manifest: &Path) -> CargoResult<()> {
+        self.rewritten.insert(manifest.to_string_lossy().into_owned(), hash_file(manifest)?);
+        Ok(())
+    }
+    /// The globs and files left out of a copy, which aren't edits to it. The
+    /// files are anchored, so they don't match files of the same name elsewhere.
+    pub fn ignored(&self, dir: &str) -> Vec<String> {
+        self.ignore.iter()
+            .cloned()
+            .chain(self.left_out.get(dir).into_iter().flat_map(|files| files).map(|file| format!("/{}", file)))
+            .collect()
+    }
+    /// Find the copies of a crate, or the copy with a directory name like `foo-1.0.0`
+    pub fn find(&self, name: &str) -> Vec<String> {
+        self.copies.iter()
+            .filter(|(dir, copy)| *dir == name || copy.name == name)
+            .map(|(dir, _)| dir.clone())
+            .collect()
+    }
+    /// Hash the current state of a copy
+    pub fn record(&mut self, name: &str, origin: Origin, source: &Path, dest: &Path) -> CargoResult<()> {
+        let files = hash_files(dest, &self.ignored(name))?;
+        self.copies.insert(name.to_string(), Copy {
+            id: origin.id,
+            name: origin.name,
+            version: origin.version,
+            source_id: origin.source_id,
+            source: source.to_string_lossy().into_owned(),
+            files
+        });
+        Ok(())
+    }
+}
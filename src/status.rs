@@ -0,0 +1,80 @@
+use cargo::CargoResult;
+use dates;
+use events;
+use manifest;
+use state::State;
+use std::{fs, path::Path};
+use sync;
+
+/// List the replacements, along with the notes on why they were made, and
+/// the copies, along with where they came from and whether they were edited.
+/// Returns false if any replacement is overdue for a review.
+pub fn print(basedir: &Path) -> CargoResult<bool> {
+    let state = State::load(basedir)?;
+    if state.replace.is_empty() && state.copies.is_empty() {
+        events::say("Nothing is patched");
+        return Ok(true);
+    }
+    let today = dates::today();
+    let mut current = true;
+    for (name, url) in &state.replace {
+        events::say(format!("{} -> {}", name, url));
+        if let Some(annotation) = state.annotations.get(name) {
+            if let Some(ref owner) = annotation.owner {
+                events::say(format!("    owner: {}", owner));
+            }
+            if let Some(ref reason) = annotation.reason {
+                events::say(format!("    reason: {}", reason));
+            }
+            if let Some(ref upstream) = annotation.upstream {
+                events::say(format!("    upstream: {}", upstream));
+            }
+            if let Some(expired) = annotation.expired(today) {
+                events::say(format!("    warning: {}, check whether it's still needed", expired));
+                current = false;
+            }
+        }
+    }
+
+    if !state.copies.is_empty() {
+        events::say("");
+        events::say(format!("Copies in {}:", basedir.display()));
+    }
+    for (dir, copy) in &state.copies {
+        events::say(dir);
+        events::say(format!("    copy of: {}", copy.id));
+        events::say(format!("    from: {}", copy.source));
+
+        let copied = basedir.join(dir);
+        let manifest = copied.join("Cargo.toml");
+        if !manifest.exists() {
+            events::say("    warning: the copy is missing");
+            continue;
+        }
+        let contents = fs::read_to_string(&manifest)?;
+        for (name, url) in &state.replace {
+            if manifest::depends_on(&contents, name)? {
+                events::say(format!("    uses: {} -> {}", name, url));
+            }
+        }
+
+        let original = Path::new(&copy.source);
+        if !original.exists() {
+            events::say("    edits: unknown, the original is gone");
+            continue;
+        }
+        let edits = sync::edits(original, &copied, &state.ignored(dir))?;
+        if edits.changed.is_empty() && edits.removed.is_empty() {
+            events::say("    edits: none");
+        } else {
+            events::say("    edits:");
+            for file in &edits.changed {
+                events::say(format!("        modified {}", file));
+            }
+            for file in &edits.removed {
+                events::say(format!("        removed {}", file));
+            }
+        }
+    }
+    Ok(current)
+}
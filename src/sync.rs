@@ -0,0 +1,179 @@
+use cargo::{
+    CargoResult,
+    core::{Dependency, PackageId, Source, SourceId},
+    util::config::Config
+};
+use copy;
+use errors::{self, Code};
+use hash::hash_files;
+use manifest;
+use semver::Version;
+use state::{Origin, State};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command
+};
+use versions;
+
+/// Files cargo creates when unpacking a crate, which aren't edits
+const IGNORED: &[&str] = &["Cargo.toml", ".cargo-ok", ".cargo_vcs_info.json"];
+
+/// The local edits of a copy compared to its original
+pub struct Edits {
+    /// Changed or added files
+    pub changed: Vec<String>,
+    pub removed: Vec<String>
+}
+/// Files left out of the copy by the built-in rules or the globs in `patterns`
+/// aren't edits, and aren't even read.
+pub fn edits(original: &Path, copy: &Path, patterns: &[String]) -> CargoResult<Edits> {
+    let original = hash_files(original, patterns)?;
+    let copied = hash_files(copy, patterns)?;
+    let ignored = |file: &str| IGNORED.contains(&file);
+    Ok(Edits {
+        changed: copied.iter()
+            .filter(|&(file, hash)| !ignored(file) && original.get(file) != Some(hash))
+            .map(|(file, _)| file.clone())
+            .collect(),
+        removed: original.keys()
+            .filter(|file| !ignored(file) && !copied.contains_key(*file))
+            .cloned()
+            .collect()
+    })
+}
+
+/// Move the copy of `name` onto the newest compatible upstream version,
+/// reapplying the local edits with a three-way merge. Manifests in
+/// `manifests` pointing to the old copy are pointed to the new one.
+pub fn sync(config: &Config, basedir: &Path, manifests: &[PathBuf], name: &str) -> CargoResult<()> {
+    let mut state = State::load(basedir)?;
+    let matching = state.find(name);
+    let dir = match matching.len() {
+        1 => matching[0].clone(),
+        0 => return Err(errors::error(Code::CopyNotFound, format!("There is no copy of {} in {}", name, basedir.display()))),
+        _ => return Err(errors::error(Code::CopyNotFound, format!(
+            "There are several copies of {}, pick one of: {}",
+            name, matching.join(", ")
+        )))
+    };
+    let (origin, base) = {
+        let copy = &state.copies[&dir];
+        (copy.origin(), PathBuf::from(&copy.source))
+    };
+    if origin.source_id.is_empty() {
+        return Err(errors::error(Code::UnknownSource, format!(
+            "Can't tell where {} came from, it was copied by an older cargo-patch. Copy it again first", dir
+        )));
+    }
+    let name = origin.name.as_str();
+    let version = Version::parse(&origin.version)?;
+    let source_id = SourceId::from_url(&origin.source_id)?;
+    if !source_id.is_registry() {
+        return Err(errors::error(Code::UnsyncableSource, format!("{} comes from {}", dir, origin.source_id)));
+    }
+
+    config.shell().status("Looking", format!("for a new version of {}", name))?;
+    let mut source = source_id.load(config)?;
+    source.update()?;
+    let req = versions::loosen(&version).to_string();
+    let dependency = Dependency::parse_no_deprecated(name, Some(&req), &source_id)?;
+    let mut newest: Option<PackageId> = None;
+    source.query(&dependency, &mut |summary| {
+        let newer = match newest {
+            Some(ref newest) => summary.version() > newest.version(),
+            None => true
+        };
+        if newer {
+            newest = Some(summary.package_id().clone());
+        }
+    })?;
+    let newest = match newest {
+        Some(ref newest) if *newest.version() > version => newest.clone(),
+        _ => {
+            config.shell().status("Fresh", format!("{} {} is already the newest compatible version", name, version))?;
+            return Ok(());
+        }
+    };
+    let upstream = source.download(&newest)?;
+    let upstream_root = upstream.root().to_path_buf();
+
+    let old = basedir.join(&dir);
+    let new_dir = format!("{}-{}", name, newest.version());
+    let new = basedir.join(&new_dir);
+    if new.exists() {
+        return Err(errors::error(Code::CopyExists, format!("Remove {} first", new.display())));
+    }
+    config.shell().status("Copying", format!("{} {}", name, newest.version()))?;
+    copy::package(&upstream_root, &new, &state.ignore)?;
+
+    // Reapply the edits, which are the differences between the copy and its original
+    let edits = edits(&base, &old, &state.ignored(&dir))?;
+    let mut conflicts = Vec::new();
+    for file in &edits.changed {
+        let ours = old.join(file);
+        let theirs = new.join(file);
+        if let Some(parent) = theirs.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if !base.join(file).exists() {
+            config.shell().status("Adding", file)?;
+            fs::copy(&ours, &theirs)?;
+        } else if !theirs.exists() {
+            config.shell().warn(format!("conflict in {}: removed upstream, keeping the edited version", file))?;
+            fs::copy(&ours, &theirs)?;
+            conflicts.push(file.clone());
+        } else {
+            let output = Command::new("git")
+                .arg("merge-file").arg("-p")
+                .arg("-L").arg("local").arg("-L").arg("original").arg("-L").arg("upstream")
+                .arg(&ours).arg(base.join(file)).arg(&theirs)
+                .output()?;
+            fs::write(&theirs, &output.stdout)?;
+            match output.status.code() {
+                Some(0) => config.shell().status("Merged", file)?,
+                Some(code) if code > 0 => {
+                    config.shell().warn(format!("conflict in {}", file))?;
+                    conflicts.push(file.clone());
+                },
+                _ => {
+                    config.shell().warn(format!("git merge-file failed on {}: {}", file, String::from_utf8_lossy(&output.stderr).trim()))?;
+                    conflicts.push(file.clone());
+                }
+            }
+        }
+    }
+    for file in &edits.removed {
+        if new.join(file).exists() {
+            config.shell().status("Removing", file)?;
+            fs::remove_file(new.join(file))?;
+        }
+    }
+
+    // The new manifest needs the same rewritten dependencies as the old one
+    let new_manifest = manifest::carry_over(
+        &fs::read_to_string(old.join("Cargo.toml"))?,
+        &fs::read_to_string(new.join("Cargo.toml"))?
+    )?;
+    fs::write(new.join("Cargo.toml"), new_manifest)?;
+
+    for path in manifests.iter().cloned().chain(state.copies.keys().map(|dir| basedir.join(dir).join("Cargo.toml"))) {
+        if let Some(rewritten) = manifest::repoint(&fs::read_to_string(&path)?, name, &old, &new)? {
+            fs::write(&path, rewritten)?;
+            config.shell().status("Rewrote", path.display())?;
+        }
+    }
+
+    state.copies.remove(&dir);
+    state.left_out.remove(&dir);
+    state.record(&new_dir, Origin::new(&newest), &upstream_root, &new)?;
+    state.save(basedir)?;
+
+    config.shell().status("Synced", format!("{} to {}", dir, new_dir))?;
+    if !conflicts.is_empty() {
+        let files: Vec<String> = conflicts.iter().map(|file| format!("  {}", new.join(file).display())).collect();
+        config.shell().warn(format!("these files have conflicts to resolve:\n{}", files.join("\n")))?;
+    }
+    config.shell().status("Kept", format!("the old copy in {}, remove it once you're done", old.display()))?;
+    Ok(())
+}
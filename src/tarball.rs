@@ -0,0 +1,80 @@
+use cargo::{CargoResult, util::Config};
+use http;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio}
+};
+
+const EXTENSIONS: &[&str] = &[".tar.gz", ".tgz", ".crate"];
+
+/// Where downloads are unpacked, inside the base directory
+pub const DIR: &str = ".tarballs";
+
+/// Replacements with another version published on crates.io are written as `version:<version>`
+pub const VERSION: &str = "version:";
+
+/// Where to download the replacement for `name` from, and where to unpack
+/// it to, if it's a tarball, a `.crate` file or a published version
+pub fn download(basedir: &Path, name: &str, url: &str) -> Option<(String, PathBuf)> {
+    if url.starts_with(VERSION) {
+        let version = &url[VERSION.len()..];
+        let source = format!("https://crates.io/api/v1/crates/{}/{}/download", name, version);
+        Some((source, basedir.join(DIR).join(format!("{}-{}", name, version))))
+    } else if is_tarball(url) {
+        Some((url.to_string(), dir(basedir, url)))
+    } else {
+        None
+    }
+}
+
+/// Returns true if the replacement is a gzipped tarball or a `.crate` file to download
+fn is_tarball(url: &str) -> bool {
+    (url.starts_with("https://") || url.starts_with("http://"))
+        && EXTENSIONS.iter().any(|extension| url.split('?').next().unwrap_or(url).ends_with(extension))
+}
+
+/// Where a tarball is unpacked to, named after its file like `foo-1.2.3`
+fn dir(basedir: &Path, url: &str) -> PathBuf {
+    let file = url.split('?').next().unwrap_or(url).rsplit('/').next().unwrap_or(url);
+    let name = EXTENSIONS.iter()
+        .find(|extension| file.ends_with(**extension))
+        .map(|extension| &file[..file.len() - extension.len()])
+        .unwrap_or(file);
+    basedir.join(DIR).join(name)
+}
+
+/// Download the tarball and unpack it into `dir`, leaving out the top level
+/// directory both crates.io and git hosts put everything in. An existing
+/// directory is reused, since tarballs of a release don't change.
+/// Returns false if it couldn't be downloaded or unpacked.
+pub fn unpack(config: &Config, url: &str, dir: &Path) -> CargoResult<bool> {
+    if dir.exists() {
+        return Ok(true);
+    }
+    let body = match http::get(config, url)? {
+        Some(body) => body,
+        None => return Ok(false)
+    };
+    // Unpacked next to where it goes, so a failed unpack doesn't leave a directory to reuse
+    let partial = PathBuf::from(format!("{}.partial", dir.display()));
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(&partial)?;
+    let mut tar = Command::new("tar")
+        .args(&["xzf", "-", "--strip-components=1"])
+        .current_dir(&partial)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = tar.stdin.take() {
+        stdin.write_all(&body)?;
+    }
+    if !tar.wait()?.success() {
+        fs::remove_dir_all(&partial)?;
+        return Ok(false);
+    }
+    fs::rename(&partial, dir)?;
+    Ok(true)
+}
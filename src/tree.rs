@@ -0,0 +1,145 @@
+use events;
+use graph::{Graph, Node, SourceKind};
+use state::State;
+use std::{
+    collections::{HashSet, VecDeque},
+    path::Path
+};
+use urls;
+
+/// Returns true if the package is a replacement
+fn is_replacement(node: &Node, state: &State) -> bool {
+    state.replace.get(&node.name)
+        .map(|url| match urls::local_path(url) {
+            Some(path) => node.source.kind == SourceKind::Path && node.root().starts_with(path),
+            None => node.source.kind == SourceKind::Git && urls::canonicalize(url) == urls::canonicalize(&node.source.url)
+        })
+        .unwrap_or(false)
+}
+/// Describe how a package was patched, if it was
+fn annotation(node: &Node, state: &State, basedir: &Path) -> Option<String> {
+    if node.manifest_path.starts_with(basedir) {
+        let dir = node.root().file_name().map(|name| name.to_string_lossy().into_owned());
+        let source = dir.as_ref()
+            .and_then(|dir| state.copies.get(dir))
+            .map(|copy| copy.id.clone())
+            .unwrap_or_else(|| String::from("unknown source"));
+        return Some(format!("patched copy of {}", source));
+    }
+    if is_replacement(node, state) {
+        return Some(format!("replaced by {}", node.source.url));
+    }
+    None
+}
+
+/// Print the dependency tree of `root`. Unless `full` is set, only the
+/// branches leading to patched packages are shown.
+pub fn print(graph: &Graph, root: usize, state: &State, basedir: &Path, full: bool) {
+    let annotations: Vec<Option<String>> = graph.nodes.iter()
+        .map(|node| annotation(node, state, basedir))
+        .collect();
+    let replaced: Vec<bool> = graph.nodes.iter().map(|node| is_replacement(node, state)).collect();
+    let relevant = if full {
+        vec![true; graph.nodes.len()]
+    } else {
+        // Everything on the way to a replacement
+        let mut relevant = replaced.clone();
+        for i in graph.dependents(&[root], |node| is_replacement(node, state)) {
+            relevant[i] = true;
+        }
+        relevant[root] = true;
+        relevant
+    };
+
+    fn visit(
+        graph: &Graph,
+        i: usize,
+        prefix: &str,
+        annotations: &[Option<String>],
+        replaced: &[bool],
+        relevant: &[bool],
+        seen: &mut HashSet<usize>
+    ) {
+        let deps: Vec<usize> = graph.nodes[i].deps.iter().cloned().filter(|&dep| relevant[dep]).collect();
+        for (n, &dep) in deps.iter().enumerate() {
+            let last = n + 1 == deps.len();
+            let node = &graph.nodes[dep];
+            let repeated = !seen.insert(dep);
+            events::say(format!(
+                "{}{}{} {}{}{}",
+                prefix,
+                if last { "└── " } else { "├── " },
+                node.name,
+                node.version,
+                annotations[dep].as_ref().map(|note| format!(" [{}]", note)).unwrap_or_default(),
+                if repeated && !node.deps.is_empty() { " (*)" } else { "" }
+            ));
+            if !repeated && !replaced[dep] {
+                let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                visit(graph, dep, &prefix, annotations, replaced, relevant, seen);
+            }
+        }
+    }
+
+    let node = &graph.nodes[root];
+    events::say(format!("{} {}", node.name, node.version));
+    let mut seen = HashSet::new();
+    seen.insert(root);
+    visit(graph, root, "", &annotations, &replaced, &relevant, &mut seen);
+}
+
+/// The shortest path of dependencies from `from` to a package `to` accepts
+fn shortest_path<F>(graph: &Graph, from: usize, to: F) -> Option<Vec<usize>>
+    where F: Fn(usize) -> bool
+{
+    let mut parents = vec![None; graph.nodes.len()];
+    let mut queue = VecDeque::new();
+    let mut seen = vec![false; graph.nodes.len()];
+    seen[from] = true;
+    queue.push_back(from);
+    while let Some(i) = queue.pop_front() {
+        if to(i) {
+            let mut path = vec![i];
+            let mut current = i;
+            while let Some(parent) = parents[current] {
+                path.push(parent);
+                current = parent;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for &dep in &graph.nodes[i].deps {
+            if !seen[dep] {
+                seen[dep] = true;
+                parents[dep] = Some(i);
+                queue.push_back(dep);
+            }
+        }
+    }
+    None
+}
+
+/// Print why each copy was made: the chain of dependencies from `root`
+/// through the copy to a replacement
+pub fn chains(graph: &Graph, root: usize, state: &State, basedir: &Path) {
+    let copies: Vec<usize> = (0..graph.nodes.len())
+        .filter(|&i| graph.nodes[i].manifest_path.starts_with(basedir))
+        .collect();
+    if copies.is_empty() {
+        events::say("Nothing was copied");
+        return;
+    }
+    let name = |i: &usize| format!("{} {}", graph.nodes[*i].name, graph.nodes[*i].version);
+    for i in copies {
+        let to_copy = shortest_path(graph, root, |j| j == i);
+        let to_replacement = shortest_path(graph, i, |j| is_replacement(&graph.nodes[j], state));
+        match (to_copy, to_replacement) {
+            (Some(to_copy), Some(to_replacement)) => {
+                let chain: Vec<String> = to_copy.iter().chain(&to_replacement[1..]).map(name).collect();
+                events::say(chain.join(" → "));
+            },
+            (None, _) => events::say(format!("{} isn't used by {} anymore", name(&i), name(&root))),
+            (_, None) => events::say(format!("{} doesn't lead to a replacement anymore", name(&i)))
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use cargo::{CargoResult, core::Shell};
+use errors::{self, Code};
+use journal::{self, Journal};
+use send;
+use state::{self, State};
+use std::{fs, path::Path};
+use sync;
+use tarball;
+
+/// Put back every manifest that was rewritten outside of the copies, remove
+/// the files cargo-patch created, and forget the replacements. Nothing is
+/// done if any of them were edited since, unless `force` is set. With
+/// `remove_copies`, the copies are removed too.
+pub fn unpatch(shell: &mut Shell, basedir: &Path, remove_copies: bool, force: bool) -> CargoResult<()> {
+    if Journal::load(basedir)?.is_some() {
+        return Err(errors::error(Code::InterruptedRun, "Finish it with --resume first, so that it can be undone"));
+    }
+    let mut state = State::load(basedir)?;
+    if state.replace.is_empty() && state.originals.is_empty() && state.created.is_empty() {
+        shell.status("Fresh", "nothing has been patched")?;
+    }
+    if !force {
+        let mut edited = Vec::new();
+        for path in state.originals.keys().chain(&state.created) {
+            if state.edited(path)? {
+                edited.push(format!("  {}", path));
+            }
+        }
+        if !edited.is_empty() {
+            return Err(errors::error(Code::CopiesChanged, format!(
+                "These were edited since cargo-patch rewrote them:\n{}\nPass --force to lose those edits",
+                edited.join("\n")
+            )));
+        }
+    }
+    for (path, original) in &state.originals {
+        fs::write(path, original)?;
+        shell.status("Restored", path)?;
+    }
+    for path in &state.created {
+        if Path::new(path).exists() {
+            fs::remove_file(path)?;
+            shell.status("Removed", path)?;
+        }
+    }
+
+    if remove_copies {
+        remove_owned(shell, basedir, &state)?;
+        return Ok(());
+    }
+    state.replace.clear();
+    state.annotations.clear();
+    state.originals.clear();
+    state.rewritten.clear();
+    state.created.clear();
+    state.save(basedir)?;
+    if !state.copies.is_empty() {
+        shell.status("Kept", format!("the copies in {}, pass --remove-copies to remove them too", basedir.display()))?;
+    }
+    Ok(())
+}
+
+/// Remove the copies in `state` and the files cargo-patch keeps in `basedir`,
+/// and then `basedir` itself if nothing else is left in it. Anything else in
+/// there, like forks, is left alone.
+fn remove_owned(shell: &mut Shell, basedir: &Path, state: &State) -> CargoResult<()> {
+    if !basedir.is_dir() {
+        return Ok(());
+    }
+    for dir in state.copies.keys() {
+        let copy = basedir.join(dir);
+        if copy.exists() {
+            fs::remove_dir_all(&copy)?;
+            shell.status("Removed", copy.display())?;
+        }
+    }
+    for file in &[state::FILE, journal::FILE] {
+        let path = basedir.join(file);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    for dir in &[tarball::DIR, send::DIR] {
+        let path = basedir.join(dir);
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+    }
+    if fs::read_dir(basedir)?.next().is_none() {
+        fs::remove_dir(basedir)?;
+        shell.status("Removed", basedir.display())?;
+    } else {
+        shell.status("Kept", format!("{}, it has files cargo-patch didn't make", basedir.display()))?;
+    }
+    Ok(())
+}
+
+/// Undo everything and remove the copies. With `keep_modified`, nothing is
+/// done if any copy has local edits.
+pub fn clean(shell: &mut Shell, basedir: &Path, keep_modified: bool, force: bool) -> CargoResult<()> {
+    if keep_modified {
+        let state = State::load(basedir)?;
+        let mut modified = Vec::new();
+        for (dir, copy) in &state.copies {
+            let original = Path::new(&copy.source);
+            if !original.exists() {
+                // Can't tell, so better safe than sorry
+                modified.push(format!("{} (its original is gone)", dir));
+                continue;
+            }
+            let edits = sync::edits(original, &basedir.join(dir), &state.ignored(dir))?;
+            if !edits.changed.is_empty() || !edits.removed.is_empty() {
+                modified.push(dir.clone());
+            }
+        }
+        if !modified.is_empty() {
+            let dirs: Vec<String> = modified.iter().map(|dir| format!("  {}", dir)).collect();
+            return Err(errors::error(Code::ModifiedCopies, format!("{}\nNothing was changed", dirs.join("\n"))));
+        }
+    }
+    unpatch(shell, basedir, true, force)
+}
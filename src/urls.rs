@@ -0,0 +1,307 @@
+use cargo::util::ToUrl;
+use git2;
+use semver::VersionReq;
+use std::path::Path;
+
+/// The user's `url.<base>.insteadOf` rules from their git configuration
+pub struct Rewrites {
+    rules: Vec<(String, String)>
+}
+impl Rewrites {
+    /// Read the rules, ignoring any errors reading the git config
+    pub fn load() -> Self {
+        let mut rules = Vec::new();
+        if let Ok(config) = git2::Config::open_default() {
+            if let Ok(entries) = config.entries(Some(r"url\..*\.insteadof")) {
+                for entry in &entries {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(_) => continue
+                    };
+                    let (name, value) = match (entry.name(), entry.value()) {
+                        (Some(name), Some(value)) => (name, value),
+                        _ => continue
+                    };
+                    let base = &name["url.".len()..name.len() - ".insteadof".len()];
+                    rules.push((value.to_string(), base.to_string()));
+                }
+            }
+        }
+        Self { rules }
+    }
+    /// Apply the longest matching rule to a URL, like git does
+    pub fn apply(&self, url: &str) -> String {
+        let rule = self.rules.iter()
+            .filter(|(prefix, _)| url.starts_with(&**prefix))
+            .max_by_key(|(prefix, _)| prefix.len());
+        match rule {
+            Some((prefix, base)) => format!("{}{}", base, &url[prefix.len()..]),
+            None => url.to_string()
+        }
+    }
+}
+
+/// Reduce a git URL to a form where trivially different spellings of the
+/// same repository compare equal: `git@github.com:org/x.git`,
+/// `https://GitHub.com/org/x/` and `ssh://git@github.com/org/x` all
+/// become `github.com/org/x`. Only the host is case-insensitive, since
+/// paths on most servers aren't. `file://` URLs become their path, so
+/// they match the same directory given as a path.
+pub fn canonicalize(url: &str) -> String {
+    let url = split_reference(url.trim()).0;
+
+    let mut rest = match url.find("://") {
+        Some(i) if url[..i].eq_ignore_ascii_case("file") => {
+            return url[i + 3..].trim_right_matches('/').to_string();
+        },
+        Some(i) => url[i + 3..].to_string(),
+        None => match url.find(':') {
+            // scp-like syntax, user@host:path
+            Some(i) if !url[..i].contains('/') => format!("{}/{}", &url[..i], &url[i + 1..]),
+            _ => url.to_string()
+        }
+    };
+    if let Some(i) = rest.find('@') {
+        if !rest[..i].contains('/') {
+            rest = rest[i + 1..].to_string();
+        }
+    }
+
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (&*rest, "")
+    };
+    let mut path = path.trim_right_matches('/');
+    if path.ends_with(".git") {
+        path = &path[..path.len() - ".git".len()];
+    }
+    format!("{}{}", host.to_lowercase(), path.trim_right_matches('/'))
+}
+
+/// Returns true if both URLs refer to the same repository
+pub fn same_source(rewrites: &Rewrites, a: &str, b: &str) -> bool {
+    canonicalize(&rewrites.apply(a)) == canonicalize(&rewrites.apply(b))
+}
+
+/// Replacements from local checkouts are written as `path:<directory>`
+const PATH: &str = "path:";
+
+/// Replacements with a fork published under another name are written as
+/// `package:<name>@<version requirement>`
+const PACKAGE: &str = "package:";
+
+/// If the replacement is a fork published under another name, return that
+/// name and the version requirement on it, which defaults to any version
+pub fn renamed(url: &str) -> Option<(&str, &str)> {
+    if !url.starts_with(PACKAGE) {
+        return None;
+    }
+    let rest = &url[PACKAGE.len()..];
+    Some(match rest.find('@') {
+        Some(at) => (&rest[..at], &rest[at + 1..]),
+        None => (rest, "*")
+    })
+}
+
+/// The keys that pick what to check out of a git repository, written
+/// after the URL like cargo does, as in `url?branch=dev`
+pub const REFERENCES: &[&str] = &["branch", "tag", "rev"];
+
+/// Split a git URL into the repository and the branch, tag or rev to use, if any
+pub fn split_reference(url: &str) -> (&str, Option<(&str, &str)>) {
+    let i = match url.find('?') {
+        Some(i) => i,
+        None => return (url, None)
+    };
+    let query = &url[i + 1..];
+    match query.find('=') {
+        Some(equals) => (&url[..i], Some((&query[..equals], &query[equals + 1..]))),
+        None => (&url[..i], Some((query, "")))
+    }
+}
+
+/// If the replacement is a local checkout, return its directory
+pub fn local_path(url: &str) -> Option<&Path> {
+    if url.starts_with(PATH) {
+        Some(Path::new(&url[PATH.len()..]))
+    } else {
+        None
+    }
+}
+
+/// Check a replacement URL for common mistakes and bring it into a form
+/// cargo accepts. Local paths, either prefixed with `path:` or recognized
+/// as existing directories, become `path:<absolute directory>`. Returns a
+/// message describing the problem if it's invalid.
+pub fn validate(url: &str, cwd: &Path) -> Result<String, String> {
+    let url = url.trim();
+    if url.is_empty() {
+        return Err(String::from("the URL is empty"));
+    }
+
+    if let Some((package, req)) = renamed(url) {
+        if package.is_empty() {
+            return Err(format!("expected a crate name after {}", PACKAGE));
+        }
+        if let Err(err) = VersionReq::parse(req) {
+            return Err(format!("invalid version requirement {:?}: {}", req, err));
+        }
+        return Ok(url.to_string());
+    }
+
+    let path = if url.starts_with(PATH) {
+        Some(&url[PATH.len()..])
+    } else if !url.contains("://") && (url.starts_with('/') || url.starts_with('.') || cwd.join(url).is_dir()) {
+        Some(url)
+    } else {
+        None
+    };
+    if let Some(path) = path {
+        // Make relative paths absolute, since manifests are copied around
+        let path = cwd.join(path);
+        if !path.is_dir() {
+            return Err(format!("{} is not a directory", path.display()));
+        }
+        return Ok(format!("{}{}", PATH, path.display()));
+    }
+
+    if url.chars().any(char::is_whitespace) {
+        return Err(format!("{:?} contains whitespace", url));
+    }
+    let (url, reference) = split_reference(url);
+    if let Some((key, value)) = reference {
+        if !REFERENCES.contains(&key) || value.is_empty() {
+            return Err(format!("expected ?branch=, ?tag= or ?rev= after the URL, not ?{}={}", key, value));
+        }
+    }
+
+    let url = match url.find("://") {
+        Some(i) => {
+            let scheme = &url[..i];
+            let rest = &url[i + 3..];
+            match scheme {
+                "https" | "http" | "ssh" | "git" => url.to_string(),
+                "git+https" | "git+ssh" => url["git+".len()..].to_string(),
+                "file" => {
+                    // Make relative paths absolute, since manifests are copied around
+                    let path = cwd.join(rest);
+                    if !path.is_dir() {
+                        return Err(format!("{} is not a directory", path.display()));
+                    }
+                    format!("file://{}", path.display())
+                },
+                _ => return Err(format!("unsupported scheme {:?}, use https, ssh, git or file", scheme))
+            }
+        },
+        None => match url.find(':') {
+            // scp-like syntax, user@host:path
+            Some(i) if url[..i].contains('@') && !url[..i].contains('/') => {
+                format!("ssh://{}/{}", &url[..i], url[i + 1..].trim_left_matches('/'))
+            },
+            _ => return Err(format!("{:?} is missing a scheme, did you mean https://{}?", url, url))
+        }
+    };
+
+    match url.to_url() {
+        Ok(_) => Ok(match reference {
+            Some((key, value)) => format!("{}?{}={}", url, key, value),
+            None => url
+        }),
+        Err(err) => Err(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn canonicalize_spellings() {
+        for url in &[
+            "git@github.com:org/x.git",
+            "https://github.com/org/x/",
+            "https://GitHub.com/org/x.git",
+            "ssh://git@github.com/org/x",
+            "git://github.com/org/x?branch=dev"
+        ] {
+            assert_eq!(canonicalize(url), "github.com/org/x");
+        }
+    }
+
+    #[test]
+    fn canonicalize_keeps_path_case() {
+        assert_eq!(canonicalize("https://github.com/Org/X"), "github.com/Org/X");
+        assert_ne!(canonicalize("https://example.com/Org/x"), canonicalize("https://example.com/org/x"));
+    }
+
+    #[test]
+    fn canonicalize_files() {
+        assert_eq!(canonicalize("file:///home/me/x/"), "/home/me/x");
+        assert_eq!(canonicalize("file:///home/me/x"), canonicalize("/home/me/x"));
+        assert_eq!(canonicalize("file:///home/me/x.git"), "/home/me/x.git");
+    }
+
+    #[test]
+    fn rewrites() {
+        let rewrites = Rewrites {
+            rules: vec![
+                (String::from("gh:"), String::from("https://github.com/")),
+                (String::from("gh:me/"), String::from("ssh://git@github.com/me/"))
+            ]
+        };
+        assert_eq!(rewrites.apply("gh:org/x"), "https://github.com/org/x");
+        assert_eq!(rewrites.apply("gh:me/x"), "ssh://git@github.com/me/x");
+        assert_eq!(rewrites.apply("https://gitlab.com/org/x"), "https://gitlab.com/org/x");
+        assert!(same_source(&rewrites, "gh:org/x", "git@github.com:org/x.git"));
+        assert!(!same_source(&rewrites, "gh:org/x", "https://github.com/org/y"));
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(split_reference("https://github.com/org/x"), ("https://github.com/org/x", None));
+        assert_eq!(split_reference("https://github.com/org/x?tag=v1"), ("https://github.com/org/x", Some(("tag", "v1"))));
+        assert_eq!(renamed("package:foo-fork@1.2"), Some(("foo-fork", "1.2")));
+        assert_eq!(renamed("package:foo-fork"), Some(("foo-fork", "*")));
+        assert_eq!(renamed("https://github.com/org/x"), None);
+        assert_eq!(local_path("path:/copies/x"), Some(Path::new("/copies/x")));
+        assert_eq!(local_path("https://github.com/org/x"), None);
+    }
+
+    #[test]
+    fn validate_git() {
+        let cwd = env::temp_dir();
+        assert_eq!(validate(" https://github.com/org/x ", &cwd), Ok(String::from("https://github.com/org/x")));
+        assert_eq!(validate("git+https://github.com/org/x", &cwd), Ok(String::from("https://github.com/org/x")));
+        assert_eq!(validate("git@github.com:org/x.git", &cwd), Ok(String::from("ssh://git@github.com/org/x.git")));
+        assert_eq!(validate("https://github.com/org/x?branch=dev", &cwd), Ok(String::from("https://github.com/org/x?branch=dev")));
+    }
+
+    #[test]
+    fn validate_paths() {
+        let cwd = env::temp_dir();
+        let path = format!("{}{}", PATH, cwd.display());
+        assert_eq!(validate(&path, &cwd), Ok(path.clone()));
+        assert_eq!(validate(&cwd.display().to_string(), Path::new("/")), Ok(path.clone()));
+        assert!(validate("path:cargo-patch-does-not-exist", &cwd).is_err());
+    }
+
+    #[test]
+    fn validate_renamed() {
+        let cwd = env::temp_dir();
+        assert_eq!(validate("package:foo-fork@1.2", &cwd), Ok(String::from("package:foo-fork@1.2")));
+        assert!(validate("package:@1.2", &cwd).is_err());
+        assert!(validate("package:foo-fork@abc", &cwd).is_err());
+    }
+
+    #[test]
+    fn validate_invalid() {
+        let cwd = env::temp_dir();
+        assert!(validate("", &cwd).is_err());
+        assert!(validate("github.com/org/x", &cwd).is_err());
+        assert!(validate("ftp://github.com/org/x", &cwd).is_err());
+        assert!(validate("https://github.com/org/my x", &cwd).is_err());
+        assert!(validate("https://github.com/org/x?commit=abc", &cwd).is_err());
+        assert!(validate("https://github.com/org/x?rev=", &cwd).is_err());
+    }
+}
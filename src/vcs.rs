@@ -0,0 +1,31 @@
+use cargo::CargoResult;
+use git2::{Repository, StatusOptions};
+use std::path::{Path, PathBuf};
+
+/// List all Cargo.toml files with uncommitted changes in the repository
+/// containing `path`. Returns nothing if `path` isn't under version control.
+pub fn dirty_manifests(path: &Path) -> CargoResult<Vec<PathBuf>> {
+    let repo = match Repository::discover(path) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(Vec::new())
+    };
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.to_path_buf(),
+        None => return Ok(Vec::new())
+    };
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+
+    let mut dirty = Vec::new();
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        let file = match entry.path() {
+            Some(file) => workdir.join(file),
+            None => continue
+        };
+        if file.file_name().map(|name| name == "Cargo.toml").unwrap_or(false) {
+            dirty.push(file);
+        }
+    }
+    Ok(dirty)
+}
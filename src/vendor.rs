@@ -0,0 +1,72 @@
+use cargo::{
+    CargoResult,
+    util::config::{Config, ConfigValue}
+};
+use graph::{Node, Source};
+use std::{fs, path::PathBuf};
+use toml;
+
+/// Find the name of the `[source]` entry that describes `source`
+fn source_name(config: &Config, source: &Source) -> CargoResult<Option<String>> {
+    if source.default_registry {
+        return Ok(Some(String::from("crates-io")));
+    }
+    let sources = match config.get_table("source")? {
+        Some(sources) => sources.val,
+        None => return Ok(None)
+    };
+    let url = &source.url;
+    for (name, value) in sources {
+        if let ConfigValue::Table(table, _) = value {
+            for key in &["registry", "git"] {
+                if let Some(&ConfigValue::String(ref location, _)) = table.get(*key) {
+                    if location.trim_right_matches('/') == url.trim_right_matches('/') {
+                        return Ok(Some(name));
+                    }
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// If the package's source is replaced by a vendor directory,
+/// return the package's directory inside of it
+pub fn vendored_path(config: &Config, package: &Node) -> CargoResult<Option<PathBuf>> {
+    let mut name = match source_name(config, &package.source)? {
+        Some(name) => name,
+        None => return Ok(None)
+    };
+
+    // Follow the chain of replacements, stopping at loops
+    let mut seen = Vec::new();
+    loop {
+        if let Some(directory) = config.get_path(&format!("source.{}.directory", name))? {
+            let directory = directory.val;
+            let candidates = [
+                directory.join(format!("{}-{}", package.name, package.version)),
+                directory.join(&package.name)
+            ];
+            for candidate in &candidates {
+                let manifest = candidate.join("Cargo.toml");
+                if !manifest.exists() {
+                    continue;
+                }
+                // `cargo vendor` only suffixes the version when there are duplicates
+                let parsed: toml::Value = toml::from_str(&fs::read_to_string(&manifest)?)?;
+                let version = parsed.get("package").and_then(|p| p.get("version")).and_then(|v| v.as_str());
+                if version == Some(&*package.version.to_string()) {
+                    return Ok(Some(candidate.clone()));
+                }
+            }
+            return Ok(None);
+        }
+        match config.get_string(&format!("source.{}.replace-with", name))? {
+            Some(next) if !seen.contains(&next.val) => {
+                seen.push(name);
+                name = next.val;
+            },
+            _ => return Ok(None)
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use cargo::{
+    CargoResult,
+    core::{Dependency, PackageId, Shell, Source, SourceId, GitReference},
+    sources::{GitSource, PathSource},
+    util::{config::Config, ToUrl}
+};
+use dates;
+use git2::Repository;
+use graph::Graph;
+use semver::{Version, VersionReq};
+use urls;
+
+/// A dependent whose version requirement on a replaced crate
+/// isn't satisfied by the replacement
+pub struct Violation {
+    pub dependent: String,
+    pub name: String,
+    pub req: VersionReq,
+    pub version: Version
+}
+
+/// What a replacement turned out to contain
+pub struct Replacement {
+    pub version: Version,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub authors: Vec<String>,
+    /// The date and short hash of the commit that was checked out
+    pub commit: Option<(String, String)>
+}
+impl Replacement {
+    /// Show what the replacement is, so that pointing to the wrong fork or a stale branch stands out
+    pub fn print(&self, shell: &mut Shell, name: &str, url: &str) -> CargoResult<()> {
+        let mut lines = vec![format!("{} {} from {}", name, self.version, url)];
+        if let Some(ref description) = self.description {
+            lines.push(format!("    {}", description.trim()));
+        }
+        if let Some(ref repository) = self.repository {
+            lines.push(format!("    repository: {}", repository));
+        }
+        if !self.authors.is_empty() {
+            lines.push(format!("    authors: {}", self.authors.join(", ")));
+        }
+        if let Some((ref date, ref hash)) = self.commit {
+            lines.push(format!("    commit: {} from {}", hash, date));
+        }
+        shell.status("Found", lines.join("\n"))
+    }
+}
+
+/// Fetch the git repository, or look through the local checkout, and look
+/// at the crate called `name` inside it
+pub fn replacement<'cfg>(config: &'cfg Config, name: &str, url: &str) -> CargoResult<Option<Replacement>> {
+    let renamed = urls::renamed(url);
+    let (source_id, mut source): (SourceId, Box<Source + 'cfg>) = match urls::local_path(url) {
+        _ if renamed.is_some() => {
+            let source_id = SourceId::crates_io(config)?;
+            let source = source_id.load(config)?;
+            (source_id, source)
+        },
+        Some(path) => {
+            let source_id = SourceId::for_path(path)?;
+            let source = PathSource::new_recursive(path, &source_id, config);
+            (source_id, Box::new(source))
+        },
+        None => {
+            let (url, reference) = urls::split_reference(url);
+            let reference = match reference {
+                Some(("tag", tag)) => GitReference::Tag(tag.to_string()),
+                Some(("rev", rev)) => GitReference::Rev(rev.to_string()),
+                Some((_, branch)) => GitReference::Branch(branch.to_string()),
+                None => GitReference::Branch(String::from("master"))
+            };
+            let source_id = SourceId::for_git(&url.to_url()?, reference)?;
+            let source = GitSource::new(&source_id, config)?;
+            (source_id, Box::new(source))
+        }
+    };
+    source.update()?;
+
+    let dependency = match renamed {
+        Some((package, req)) => Dependency::parse_no_deprecated(package, Some(req), &source_id)?,
+        None => Dependency::parse_no_deprecated(name, None, &source_id)?
+    };
+    // The registry has every published version, of which the newest is used
+    let mut id: Option<PackageId> = None;
+    source.query(&dependency, &mut |summary| {
+        if id.as_ref().map(|id| summary.version() > id.version()).unwrap_or(true) {
+            id = Some(summary.package_id().clone());
+        }
+    })?;
+    let id = match id {
+        Some(id) => id,
+        None => return Ok(None)
+    };
+    let package = source.download(&id)?;
+    let metadata = package.manifest().metadata();
+
+    // Checkouts are git repositories of their own
+    let commit = Repository::discover(package.root()).ok()
+        .and_then(|repo| repo.head().ok()
+            .and_then(|head| head.target())
+            .and_then(|oid| repo.find_commit(oid).ok())
+            .map(|commit| {
+                let date = dates::format(commit.time().seconds() / (60 * 60 * 24));
+                let hash = commit.id().to_string();
+                (date, hash[..7].to_string())
+            }));
+
+    Ok(Some(Replacement {
+        version: id.version().clone(),
+        description: metadata.description.clone(),
+        repository: metadata.repository.clone(),
+        authors: metadata.authors.clone(),
+        commit
+    }))
+}
+
+/// Find the newest version of `name` published on crates.io
+pub fn newest_published(config: &Config, name: &str) -> CargoResult<Option<Version>> {
+    let source_id = SourceId::crates_io(config)?;
+    let mut source = source_id.load(config)?;
+    source.update()?;
+
+    let dependency = Dependency::parse_no_deprecated(name, None, &source_id)?;
+    let mut newest: Option<Version> = None;
+    source.query(&dependency, &mut |summary| {
+        if newest.as_ref().map(|newest| summary.version() > newest).unwrap_or(true) {
+            newest = Some(summary.version().clone());
+        }
+    })?;
+    Ok(newest)
+}
+
+/// Find every package in the graph that depends on `name` with a version
+/// requirement that `version` doesn't match
+pub fn violations(graph: &Graph, name: &str, version: &Version) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for package in &graph.nodes {
+        for dep in &package.dependencies {
+            if dep.name == name && !dep.req.matches(version) {
+                violations.push(Violation {
+                    dependent: package.id.clone(),
+                    name: name.to_string(),
+                    req: dep.req.clone(),
+                    version: version.clone()
+                });
+            }
+        }
+    }
+    violations
+}
+
+/// If the graph has several versions of `name`, find the ones that a
+/// replacement with `version` isn't semver-compatible with
+pub fn incompatible(graph: &Graph, name: &str, version: &Version) -> Vec<usize> {
+    let versions: Vec<usize> = graph.nodes.iter()
+        .enumerate()
+        .filter(|&(_, node)| node.name == name)
+        .map(|(i, _)| i)
+        .collect();
+    if versions.len() < 2 {
+        return Vec::new();
+    }
+    let compatible = loosen(version);
+    versions.into_iter().filter(|&i| !compatible.matches(&graph.nodes[i].version)).collect()
+}
+
+/// Lay out the violations as a table, one per line
+pub fn table(violations: &[Violation]) -> String {
+    let rows: Vec<(String, String, String)> = violations.iter()
+        .map(|v| (v.dependent.clone(), v.req.to_string(), format!("{} {}", v.name, v.version)))
+        .collect();
+    let headers = ("DEPENDENT", "REQUIRED", "REPLACEMENT");
+
+    let width0 = rows.iter().map(|r| r.0.len()).chain(Some(headers.0.len())).max().unwrap_or(0);
+    let width1 = rows.iter().map(|r| r.1.len()).chain(Some(headers.1.len())).max().unwrap_or(0);
+
+    let mut lines = vec![format!("{:w0$}  {:w1$}  {}", headers.0, headers.1, headers.2, w0 = width0, w1 = width1)];
+    for (dependent, req, replacement) in &rows {
+        lines.push(format!("{:w0$}  {:w1$}  {}", dependent, req, replacement, w0 = width0, w1 = width1));
+    }
+    lines.join("\n")
+}
+
+/// Create the narrowest caret requirement that accepts `version`, e.g.
+/// `^0.9` for 0.9.2 or `^1` for 1.4.0. Prereleases can only be matched exactly.
+pub fn loosen(version: &Version) -> VersionReq {
+    let req = if version.is_prerelease() {
+        format!("={}", version)
+    } else if version.major > 0 {
+        format!("^{}", version.major)
+    } else if version.minor > 0 {
+        format!("^0.{}", version.minor)
+    } else {
+        format!("^0.0.{}", version.patch)
+    };
+    VersionReq::parse(&req).expect("generated version requirement should be valid")
+}
+
+pub fn print_loosened(shell: &mut Shell, violations: &[Violation], dropped: bool) -> CargoResult<()> {
+    for violation in violations {
+        if dropped {
+            shell.status("Dropped", format!("{}'s requirement on {} ({})", violation.dependent, violation.name, violation.req))?;
+        } else {
+            shell.status("Loosened", format!("{}'s requirement on {} from {} to {}",
+                violation.dependent, violation.name, violation.req, loosen(&violation.version)))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loosened(version: &str) -> VersionReq {
+        loosen(&Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn loosen_stable() {
+        assert_eq!(loosened("1.4.0"), VersionReq::parse("^1").unwrap());
+        assert_eq!(loosened("0.9.2"), VersionReq::parse("^0.9").unwrap());
+        assert_eq!(loosened("0.0.3"), VersionReq::parse("^0.0.3").unwrap());
+    }
+
+    #[test]
+    fn loosen_accepts() {
+        for version in &["1.4.0", "0.9.2", "0.0.3", "2.0.0-beta.1"] {
+            assert!(loosened(version).matches(&Version::parse(version).unwrap()));
+        }
+        assert!(loosened("1.4.0").matches(&Version::parse("1.0.0").unwrap()));
+        assert!(!loosened("1.4.0").matches(&Version::parse("2.0.0").unwrap()));
+        assert!(!loosened("0.9.2").matches(&Version::parse("0.10.0").unwrap()));
+    }
+
+    #[test]
+    fn loosen_prerelease() {
+        assert_eq!(loosened("2.0.0-beta.1"), VersionReq::parse("=2.0.0-beta.1").unwrap());
+        assert!(!loosened("2.0.0-beta.1").matches(&Version::parse("2.0.0-beta.2").unwrap()));
+    }
+}